@@ -0,0 +1,229 @@
+#![allow(dead_code, unused)]
+
+// Vault health checks. Notes are scanned for links to notes which don't
+// exist, paths which don't resolve to a file in the vault, and notes which
+// nothing links to. The same check backs the panel in the menu and the
+// `memristor check` command so it can be run before committing.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use iced::{Element, Length, Padding};
+use iced::widget::{button, column, mouse_area, row, scrollable, text, Column};
+use iced::mouse::Interaction;
+
+use crate::error::FileSystemError;
+use crate::filetree;
+use crate::index::LinkIndex;
+use crate::links;
+use crate::rename::{self, ReferenceKind};
+use crate::settings::Settings;
+use crate::styles;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    BrokenLink { target: String },
+    MissingPath { path: String },
+    Orphan,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub file: PathBuf,
+    // Zero based, orphans aren't on a line
+    pub line: Option<usize>,
+    pub problem: Problem,
+}
+
+impl Issue {
+    // Orphans are worth knowing about but shouldn't fail a check
+    pub fn is_error(&self) -> bool {
+        !matches!(self.problem, Problem::Orphan)
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::BrokenLink { target } => write!(f, "link to missing note '{}'", target),
+            Problem::MissingPath { path } => write!(f, "'{}' doesn't resolve to a file in the vault", path),
+            Problem::Orphan => write!(f, "no other notes link here"),
+        }
+    }
+}
+
+pub struct CheckPanel {
+    root: Option<PathBuf>,
+    issues: Vec<Issue>,
+    status: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Run,
+    // Handled at the Layout level since it opens a file
+    OpenIssue(PathBuf, usize),
+}
+
+impl CheckPanel {
+    pub fn new() -> Self {
+        CheckPanel {
+            root: None,
+            issues: vec!(),
+            status: None,
+        }
+    }
+
+    pub fn update(&mut self, message: Message, settings: &Settings) {
+        match message {
+            Message::Run => {
+                let Some(root) = settings.root_dir.as_ref().map(PathBuf::from) else {
+                    self.status = Some("No directory open".into());
+                    return;
+                };
+                match check_vault(&root) {
+                    Ok(issues) => {
+                        let errors = issues.iter().filter(|issue| issue.is_error()).count();
+                        self.status = Some(format!("{} problems, {} orphans", errors, issues.len() - errors));
+                        self.issues = issues;
+                    }
+                    Err(err) => {
+                        self.status = Some(err.to_string());
+                        self.issues.clear();
+                    }
+                }
+                // Issues are for canonical paths
+                self.root = Some(fs::canonicalize(&root).unwrap_or(root));
+            }
+            Message::OpenIssue(..) => unreachable!("Handled in layout.rs"),
+        }
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let mut results = Column::new().spacing(styles::SPACING_SMALL);
+        if let Some(status) = &self.status {
+            results = results.push(text(status));
+        }
+        for issue in self.issues.iter() {
+            let location = match issue.line {
+                Some(line) => format!("{}:{}", self.display_path(&issue.file), line + 1),
+                None => self.display_path(&issue.file),
+            };
+            results = results.push(
+                mouse_area(
+                    column![
+                        text(location).size(13),
+                        text(issue.problem.to_string()).size(12),
+                    ]
+                    .width(Length::Fill)
+                )
+                .on_press(Message::OpenIssue(issue.file.clone(), issue.line.unwrap_or(0)))
+                .interaction(Interaction::Pointer)
+            );
+        }
+
+        column![
+            row![button("Check vault").on_press(Message::Run)],
+            scrollable(results).height(Length::Fill),
+        ]
+        .spacing(styles::SPACING_SMALL)
+        .padding(Padding::new(styles::SPACING_SMALL))
+        .into()
+    }
+
+    fn display_path(&self, path: &Path) -> String {
+        let relative = match &self.root {
+            Some(root) => path.strip_prefix(root.join("typst")).unwrap_or(path),
+            None => path,
+        };
+        relative.to_string_lossy().into_owned()
+    }
+}
+
+/////////// Logic ///////////////////
+
+// Paths are checked against the canonical root so a relative root like `.`
+// works, the issues are for canonical paths too
+pub fn check_vault(root_dir: &Path) -> Result<Vec<Issue>, FileSystemError> {
+    let root_dir = &fs::canonicalize(root_dir)
+        .map_err(|_| FileSystemError::ReadDirError { path: root_dir.into() })?;
+    let mut issues = vec!();
+    for file in filetree::typst_files(root_dir)? {
+        let source = fs::read_to_string(&file)
+            .map_err(|_| FileSystemError::ReadFileError { path: file.clone().into() })?;
+
+        for reference in rename::find_references(&source) {
+            let problem = match reference.kind {
+                ReferenceKind::Link(target) => {
+                    if links::resolve(root_dir, &target).is_some_and(|path| path.is_file()) {
+                        continue;
+                    }
+                    Problem::BrokenLink { target }
+                }
+                ReferenceKind::Path(path) => {
+                    let resolved = rename::resolve_path(&file, &path);
+                    if resolved.starts_with(root_dir) && resolved.is_file() {
+                        continue;
+                    }
+                    Problem::MissingPath { path }
+                }
+            };
+            issues.push(Issue { file: file.clone(), line: Some(reference.line), problem });
+        }
+    }
+
+    let index = LinkIndex::new(Some(root_dir));
+    let mut notes: Vec<&Path> = index.notes().collect();
+    notes.sort();
+    for note in notes {
+        if index.backlinks(note).is_empty() {
+            issues.push(Issue { file: note.to_path_buf(), line: None, problem: Problem::Orphan });
+        }
+    }
+    Ok(issues)
+}
+
+// The non-GUI entry point, prints every issue and returns whether the vault
+// is free of errors
+pub fn run_cli(root_dir: &Path) -> Result<bool, FileSystemError> {
+    let issues = check_vault(root_dir)?;
+    let typst_dir = fs::canonicalize(root_dir).unwrap_or_else(|_| root_dir.to_path_buf()).join("typst");
+    for issue in issues.iter() {
+        let path = issue.file.strip_prefix(&typst_dir).unwrap_or(&issue.file).display();
+        let level = if issue.is_error() { "error" } else { "warning" };
+        match issue.line {
+            Some(line) => println!("{}:{}: {}: {}", path, line + 1, level, issue.problem),
+            None => println!("{}: {}: {}", path, level, issue.problem),
+        }
+    }
+    Ok(!issues.iter().any(Issue::is_error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn problems_are_found() {
+        let vault = TempDir::new("memristor-check").unwrap();
+        let typst = vault.path().join("typst");
+        fs::create_dir_all(typst.join("dir")).unwrap();
+        fs::create_dir_all(vault.path().join("pdf")).unwrap();
+        fs::write(typst.join("a.typ"), "[[dir/b]] [[missing]]\n#include \"dir/b.typ\"\n#image(\"../../outside.png\")").unwrap();
+        fs::write(typst.join("dir/b.typ"), "#import \"../a.typ\"\n#import \"@preview/cetz:0.3.0\"").unwrap();
+
+        let typst = fs::canonicalize(&typst).unwrap();
+        let issues = check_vault(vault.path()).unwrap();
+        assert_eq!(issues, vec![
+            Issue { file: typst.join("a.typ"), line: Some(0), problem: Problem::BrokenLink { target: "missing".into() } },
+            Issue { file: typst.join("a.typ"), line: Some(2), problem: Problem::MissingPath { path: "../../outside.png".into() } },
+            Issue { file: typst.join("a.typ"), line: None, problem: Problem::Orphan },
+        ]);
+
+        // Like running `memristor check .` from inside the vault
+        let relative = rename::relative_path(&std::env::current_dir().unwrap(), vault.path());
+        assert_eq!(check_vault(Path::new(&relative)).unwrap(), issues);
+    }
+}
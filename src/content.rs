@@ -1,22 +1,128 @@
 #![allow(dead_code, unused)]
 
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Instant, Duration};
 
-use iced::{Element, Length, Border, Color, Background, Theme, Task};
-use iced::widget::{Row,column, container, text_editor, svg, scrollable};
+use iced::{keyboard, mouse, Alignment, Element, Length, Border, Color, Background, Theme, Task, Point, Padding, Size};
+use iced::mouse::Interaction;
+use iced::widget::{self, Row, Space, button, column, container, image, row, text, text_editor, text_input, svg, scrollable, mouse_area, responsive};
+use iced::widget::scrollable::{AbsoluteOffset, RelativeOffset, Viewport};
+use rfd::{MessageButtons, MessageDialog, MessageDialogResult};
 
-use crate::error::TypstError;
-use crate::typst::TypstContext;
+use crate::error::{TypstError, FileSystemError};
+use crate::typst::{self, TypstContext};
+use crate::links::{self, LinkRegion, LinkTarget};
+use crate::rename;
+use crate::highlighter::{self, TypstHighlighter};
+use crate::history::History;
+use crate::header;
+use crate::index::Backlink;
+use crate::outline::{self, OutlineHeading, HeadingPosition};
+use crate::session::TabSession;
+use crate::sync::{self, SourceMark};
+use crate::components::{self, hrule};
+use crate::styles;
 
 const SECONDS_BETWEEN_RENDER: u64 = 5;
 
-pub struct ContentArea {
-    open_file: Option<PathBuf>,
+// Document ids are unique across every pane since they name preview directories
+static NEXT_DOCUMENT_ID: AtomicU64 = AtomicU64::new(0);
+
+// An open file, each tab has its own buffer, history and preview
+pub struct Document {
+    id: u64,
+    pub path: PathBuf,
     content: text_editor::Content,
-    preview_files: Vec<PathBuf>,
+    history: History,
+    pages: Vec<PreviewPage>,
+    links: Vec<LinkRegion>,
+    headings: Vec<OutlineHeading>,
+    heading_positions: Vec<HeadingPosition>,
+    line_marks: Vec<SourceMark>,
+    // The line of the mark the preview was last scrolled to
+    synced_line: Option<usize>,
+    preview_offset: RelativeOffset,
+    zoom: Zoom,
+    // The scale pages were last shown at, heavy pages are rasterized to match
+    raster_scale: f32,
     next_render: Instant,
+    dirty: bool,
+}
+
+// What a render found out about the compiled document
+#[derive(Debug, Clone, Default)]
+pub struct RenderOutput {
+    pub links: Vec<LinkRegion>,
+    pub heading_positions: Vec<HeadingPosition>,
+    pub line_marks: Vec<SourceMark>,
+    // One based numbers of the pages rendered as PNGs, and at what resolution
+    pub raster_pages: Vec<usize>,
+    pub raster_ppi: f32,
+}
+
+// How big preview pages are drawn, Scale is pixels per point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Zoom {
+    FitWidth,
+    FitPage,
+    Scale(f32),
+}
+
+// These come from the zoom buttons in the ContentHeader
+#[derive(Debug, Clone, Copy)]
+pub enum ZoomMessage {
+    In,
+    Out,
+    FitWidth,
+    FitPage,
+}
+
+const PAGE_GAP: f32 = 12.0;
+const ZOOM_STEP: f32 = 1.25;
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 5.0;
+const THUMBNAIL_WIDTH: f32 = 96.0;
+const MIN_PPI: f32 = 36.0;
+const MAX_PPI: f32 = 600.0;
+
+// A rendered page of the preview, sizes are in points
+#[derive(Debug, Clone)]
+pub struct PreviewPage {
+    pub path: PathBuf,
+    pub handle: svg::Handle,
+    // Of the svg, to tell which pages changed between renders
+    pub hash: u64,
+    // Shown instead of the svg for pages which are slow to draw
+    pub raster: Option<image::Handle>,
+    pub raster_ppi: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+pub struct ContentArea {
+    documents: Vec<Document>,
+    active: Option<usize>,
+    // The tab being dragged to a new position
+    dragging: Option<usize>,
+    // Undo history for files which aren't currently open
+    histories: HashMap<PathBuf, History>,
+    // Zoom levels are remembered for files which aren't currently open too
+    zooms: HashMap<PathBuf, Zoom>,
+    // Held modifiers, Ctrl+scroll zooms the preview instead of scrolling it
+    modifiers: keyboard::Modifiers,
+    preview_id: widget::Id,
+    // The preview page under the mouse and where on it, in points
+    hover: Option<(usize, Point)>,
+    // The last known preview scroll position, used to turn page positions into offsets
+    preview_viewport: Option<Viewport>,
+    // What's typed into the go to page box
+    page_input: String,
+    pub thumbnails_open: bool,
+    pub outline_open: bool,
     pub editor_open: bool,
     pub preview_open: bool
 }
@@ -24,90 +130,660 @@ pub struct ContentArea {
 #[derive(Debug, Clone)]
 pub enum Message {
     Edit(text_editor::Action),
+    Undo,
+    Redo,
+    Save,
     OpenFile(PathBuf),
+    // Open a file with the cursor at the given line and column
+    OpenFileAt(PathBuf, usize, usize),
     OpenPreview,
-    RenderDone(Result<(), TypstError>),
+    RenderDone(u64, Result<RenderOutput, TypstError>),
+    PreviewScrolled(Viewport),
+    PreviewHovered(usize, Point),
+    PreviewClicked,
+    JumpToHeading(usize),
+    Zoom(ZoomMessage),
+    PreviewWheel(mouse::ScrollDelta),
+    // Pages are zero based
+    GoToPage(usize),
+    PreviousPage,
+    NextPage,
+    PageInput(String),
+    SubmitPage,
+    ToggleThumbnails,
+    // Handled at the Layout level since the note might need creating
+    OpenNote(String),
+    Tab(TabMessage),
+}
+
+// These come from the tab strip in the ContentHeader
+#[derive(Debug, Clone)]
+pub enum TabMessage {
+    Select(usize),
+    Close(usize),
+    Drag(usize),
+    Drop(usize),
 }
 
 impl ContentArea {
     pub fn new() -> Self {
         ContentArea {
-            open_file: None,
-            preview_files: vec!(),
-            content: text_editor::Content::new(),
-            next_render: Instant::now(),
+            documents: vec!(),
+            active: None,
+            dragging: None,
+            histories: HashMap::new(),
+            zooms: HashMap::new(),
+            modifiers: keyboard::Modifiers::default(),
+            preview_id: widget::Id::unique(),
+            hover: None,
+            preview_viewport: None,
+            page_input: String::new(),
+            thumbnails_open: false,
+            outline_open: false,
             editor_open: false,
             preview_open: true,
         }
     }
 
-    fn set_render_task(&mut self, typst: &TypstContext) -> Task<Message> {
-        // No file is open so we can't render anything
-        if self.open_file.is_none() {
-            return Task::none();
-        }
+    pub fn active_document(&self) -> Option<&Document> {
+        self.active.map(|index| &self.documents[index])
+    }
 
-        // Debounce
-        let now = Instant::now();
-        if now < self.next_render {
-            return Task::none();
-        }
-        else {
-            let next_render = now.checked_add(Duration::from_secs(SECONDS_BETWEEN_RENDER)).unwrap();
-            self.next_render = next_render;
-        }
+    fn active_document_mut(&mut self) -> Option<&mut Document> {
+        self.active.map(|index| &mut self.documents[index])
+    }
 
-        // Render
-        let preview_path = typst.preview_path.clone();
-        let content = self.content.text();
-        let open_file = self.open_file.as_ref().unwrap().clone();
-        Task::perform(
-            TypstContext::compile(preview_path, content, open_file),
-            |result| Message::RenderDone(result)
-        )
+    pub fn tabs(&self) -> Vec<header::Tab> {
+        self.documents.iter().enumerate().map(|(index, doc)| header::Tab {
+            title: doc.title(),
+            dirty: doc.dirty,
+            active: self.active == Some(index),
+        })
+        .collect()
     }
 
     pub fn update(&mut self, message: Message, typst: &TypstContext) -> Task<Message> {
         match message {
             Message::Edit(action) => {
-                self.content.perform(action);
-                self.set_render_task(typst)
+                let Some(doc) = self.active_document_mut() else {
+                    return Task::none();
+                };
+                doc.history.record(&doc.content, &action);
+                let is_edit = action.is_edit();
+                doc.dirty |= is_edit;
+                doc.content.perform(action);
+                if is_edit {
+                    doc.refresh_outline();
+                }
+                let render = doc.render_task(typst);
+                Task::batch([render, self.sync_preview()])
+            }
+            Message::Undo => {
+                let Some(doc) = self.active_document_mut() else {
+                    return Task::none();
+                };
+                if doc.history.undo(&mut doc.content) {
+                    doc.dirty = true;
+                    doc.refresh_outline();
+                    doc.render_task(typst)
+                } else {
+                    Task::none()
+                }
+            }
+            Message::Redo => {
+                let Some(doc) = self.active_document_mut() else {
+                    return Task::none();
+                };
+                if doc.history.redo(&mut doc.content) {
+                    doc.dirty = true;
+                    doc.refresh_outline();
+                    doc.render_task(typst)
+                } else {
+                    Task::none()
+                }
+            }
+            Message::Save => {
+                if let Some(doc) = self.active_document_mut() {
+                    let _ = doc.save(); // TODO surface save errors
+                }
+                Task::none()
             }
             Message::OpenFile(filepath) => {
-                let text = fs::read_to_string(filepath).expect("Could not read file");
-                self.content = text_editor::Content::with_text(&text);
                 self.editor_open = true;
-                self.set_render_task(typst)
+                if let Some(index) = self.documents.iter().position(|doc| doc.path == filepath) {
+                    return self.select(index);
+                }
+
+                let text = fs::read_to_string(&filepath).expect("Could not read file");
+                let mut history = self.histories.remove(&filepath).unwrap_or_default();
+                let content = text_editor::Content::with_text(&text);
+                history.reenter(&content);
+
+                let zoom = self.zooms.remove(&filepath).unwrap_or(Zoom::FitWidth);
+                let mut doc = Document {
+                    id: NEXT_DOCUMENT_ID.fetch_add(1, Ordering::Relaxed),
+                    path: filepath,
+                    content,
+                    history,
+                    pages: vec!(),
+                    links: vec!(),
+                    headings: vec!(),
+                    heading_positions: vec!(),
+                    line_marks: vec!(),
+                    synced_line: None,
+                    preview_offset: RelativeOffset::START,
+                    zoom,
+                    raster_scale: 1.0,
+                    next_render: Instant::now(),
+                    dirty: false,
+                };
+                doc.refresh_outline();
+                let render = doc.render_task(typst);
+                self.documents.push(doc);
+                self.active = Some(self.documents.len() - 1);
+                render
+            }
+            Message::OpenFileAt(filepath, line, column) => {
+                let task = self.update(Message::OpenFile(filepath), typst);
+                if let Some(doc) = self.active_document_mut() {
+                    let position = clamp_position(&doc.content, text_editor::Position { line, column });
+                    doc.content.move_to(text_editor::Cursor { position, selection: None });
+                }
+                task
             }
             Message::OpenPreview => {
                 self.preview_open = true;
-                self.set_render_task(typst)
+                match self.active_document_mut() {
+                    Some(doc) => doc.render_task(typst),
+                    None => Task::none(),
+                }
             }
-            Message::RenderDone(result) => {
+            Message::RenderDone(id, result) => {
+                let is_active = self.active_document().is_some_and(|doc| doc.id == id);
+                let anchor = if is_active { self.preview_anchor() } else { None };
+                let Some(doc) = self.documents.iter_mut().find(|doc| doc.id == id) else {
+                    return Task::none();
+                };
                 match result {
                     Err(_) => {} // TODO handle error
-                    Ok(()) => {
-                        match typst.get_preview_files() {
+                    Ok(output) => {
+                        match typst.get_preview_files(id) {
                             Err(_) => {},
-                            Ok(files) => self.preview_files = files,
+                            Ok(files) => {
+                                let mut rasters = typst.get_raster_files(id);
+                                rasters.retain(|page, _| output.raster_pages.contains(page));
+                                doc.pages = preview_pages(files, &doc.pages, &rasters, output.raster_ppi);
+                            }
                         }
+                        doc.links = output.links;
+                        doc.heading_positions = output.heading_positions;
+                        doc.line_marks = output.line_marks;
                     }
                 }
+                // Keep the same part of the same page in view as pages change
+                // size, the first render goes to where the preview was left
+                match anchor {
+                    Some((page, y)) => self.scroll_to_page(page, y),
+                    None if is_active => widget::operation::snap_to(self.preview_id.clone(), doc.preview_offset),
+                    None => Task::none(),
+                }
+            }
+            Message::PreviewHovered(page, point) => {
+                self.hover = Some((page, point));
+                Task::none()
+            }
+            Message::PreviewClicked => {
+                let Some(link) = self.hovered_link().cloned() else {
+                    self.sync_editor();
+                    return Task::none();
+                };
+                match link.target() {
+                    LinkTarget::Note(target) => Task::done(Message::OpenNote(target.to_string())),
+                    LinkTarget::Internal { page, y } => self.scroll_to_page(page.saturating_sub(1), y),
+                    LinkTarget::Url(url) => {
+                        open_external(url);
+                        Task::none()
+                    }
+                    LinkTarget::File { path, absolute } => self.open_linked_file(path, absolute),
+                }
+            }
+            Message::OpenNote(_) => {
+                unreachable!("Should be handled in layout");
+            }
+            Message::JumpToHeading(index) => self.jump_to_heading(index),
+            Message::Zoom(message) => {
+                let size = self.preview_viewport.map(|viewport| viewport.bounds().size());
+                if let Some(doc) = self.active_document_mut() {
+                    doc.zoom = match message {
+                        ZoomMessage::In => Zoom::Scale(doc.current_scale(size) * ZOOM_STEP),
+                        ZoomMessage::Out => Zoom::Scale(doc.current_scale(size) / ZOOM_STEP),
+                        ZoomMessage::FitWidth => Zoom::FitWidth,
+                        ZoomMessage::FitPage => Zoom::FitPage,
+                    };
+                    if let Zoom::Scale(scale) = doc.zoom {
+                        doc.zoom = Zoom::Scale(scale.clamp(MIN_ZOOM, MAX_ZOOM));
+                    }
+                    doc.raster_scale = doc.current_scale(size);
+                    // Rasterized pages need rendering again at the new resolution
+                    if doc.pages.iter().any(|page| page.raster.is_some()) {
+                        return doc.render_task(typst);
+                    }
+                }
+                Task::none()
+            }
+            Message::GoToPage(index) => self.scroll_to_page(index, 0.0),
+            Message::PreviousPage => match self.current_page() {
+                Some(page) => self.scroll_to_page(page.saturating_sub(1), 0.0),
+                None => Task::none(),
+            },
+            Message::NextPage => match self.current_page() {
+                Some(page) => self.scroll_to_page(page + 1, 0.0),
+                None => Task::none(),
+            },
+            Message::PageInput(input) => {
+                self.page_input = input;
+                Task::none()
+            }
+            Message::SubmitPage => {
+                let page = self.page_input.trim().parse::<usize>().ok();
+                self.page_input.clear();
+                match page {
+                    Some(page) => self.scroll_to_page(page.saturating_sub(1), 0.0),
+                    None => Task::none(),
+                }
+            }
+            Message::ToggleThumbnails => {
+                self.thumbnails_open = !self.thumbnails_open;
+                Task::none()
+            }
+            Message::PreviewWheel(delta) => {
+                let y = match delta {
+                    mouse::ScrollDelta::Lines { y, .. } | mouse::ScrollDelta::Pixels { y, .. } => y,
+                };
+                match y {
+                    _ if y > 0.0 => self.update(Message::Zoom(ZoomMessage::In), typst),
+                    _ if y < 0.0 => self.update(Message::Zoom(ZoomMessage::Out), typst),
+                    _ => Task::none(),
+                }
+            }
+            Message::PreviewScrolled(viewport) => {
+                self.preview_viewport = Some(viewport);
+                if let Some(doc) = self.active_document_mut() {
+                    doc.preview_offset = viewport.relative_offset();
+                    doc.raster_scale = doc.current_scale(Some(viewport.bounds().size()));
+                }
+                Task::none()
+            }
+            Message::Tab(TabMessage::Select(index)) => self.select(index),
+            Message::Tab(TabMessage::Close(index)) => {
+                self.close(index, typst);
                 Task::none()
             }
+            Message::Tab(TabMessage::Drag(index)) => {
+                self.dragging = Some(index);
+                self.select(index)
+            }
+            Message::Tab(TabMessage::Drop(index)) => {
+                match self.dragging.take() {
+                    Some(from) if from != index && from < self.documents.len() => {
+                        let doc = self.documents.remove(from);
+                        self.documents.insert(index.min(self.documents.len()), doc);
+                        self.active = Some(index.min(self.documents.len() - 1));
+                        Task::none()
+                    }
+                    _ => Task::none(),
+                }
+            }
+        }
+    }
+
+    fn hovered_link(&self) -> Option<&LinkRegion> {
+        let (page, point) = self.hover?;
+        self.active_document()?.links.iter()
+            .find(|link| link.page == page + 1 && link.contains(point.x, point.y))
+    }
+
+    // Renders every open document again straight away, after the way
+    // previews are rendered changed
+    pub fn render_all(&mut self, typst: &TypstContext) -> Task<Message> {
+        Task::batch(self.documents.iter_mut().map(|doc| {
+            doc.next_render = Instant::now();
+            doc.render_task(typst)
+        }))
+    }
+
+    // The open tabs with where the cursor and preview were, for the session
+    pub fn tab_sessions(&self) -> Vec<TabSession> {
+        self.documents.iter()
+            .map(|doc| {
+                let position = doc.content.cursor().position;
+                TabSession {
+                    path: doc.path.to_string_lossy().into_owned(),
+                    line: position.line,
+                    column: position.column,
+                    preview_y: doc.preview_offset.y,
+                }
+            })
+            .collect()
+    }
+
+    // Reopens the tabs of a saved session, skipping notes which were
+    // deleted since
+    pub fn restore(&mut self, tabs: &[TabSession], active: Option<usize>, typst: &TypstContext) -> Task<Message> {
+        let mut tasks = vec!();
+        let mut active_index = None;
+        for (index, tab) in tabs.iter().enumerate() {
+            let path = PathBuf::from(&tab.path);
+            if !path.is_file() || self.has_document(&path) {
+                continue;
+            }
+            tasks.push(self.update(Message::OpenFileAt(path, tab.line, tab.column), typst));
+            if let Some(doc) = self.active_document_mut() {
+                doc.preview_offset = RelativeOffset { x: 0.0, y: tab.preview_y.clamp(0.0, 1.0) };
+            }
+            if active == Some(index) {
+                active_index = self.active;
+            }
+        }
+        if let Some(index) = active_index {
+            tasks.push(self.select(index));
+        }
+        Task::batch(tasks)
+    }
+
+    pub fn set_modifiers(&mut self, modifiers: keyboard::Modifiers) {
+        self.modifiers = modifiers;
+    }
+
+    pub fn unsaved_documents(&self) -> Vec<PathBuf> {
+        self.documents.iter().filter(|doc| doc.dirty).map(|doc| doc.path.clone()).collect()
+    }
+
+    pub fn save_document(&mut self, path: &Path) -> Result<(), FileSystemError> {
+        for doc in self.documents.iter_mut().filter(|doc| doc.path == path && doc.dirty) {
+            doc.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn has_unsaved(&self, path: &Path) -> bool {
+        self.documents.iter().any(|doc| doc.path == path && doc.dirty)
+    }
+
+    pub fn has_document(&self, path: &PathBuf) -> bool {
+        self.documents.iter().any(|doc| doc.path == *path)
+    }
+
+    // Follow a file which was renamed or moved
+    pub fn rename_document(&mut self, from: &Path, to: &Path) {
+        for doc in self.documents.iter_mut().filter(|doc| doc.path == from) {
+            doc.path = to.to_path_buf();
+        }
+        if let Some(history) = self.histories.remove(from) {
+            self.histories.insert(to.to_path_buf(), history);
+        }
+        if let Some(zoom) = self.zooms.remove(from) {
+            self.zooms.insert(to.to_path_buf(), zoom);
+        }
+    }
+
+    // Repeat an edit made in another pane at the cursor it was made with, so
+    // only the changed part of the buffer is touched and it can be undone here
+    pub fn apply_edit(&mut self, path: &PathBuf, cursor: text_editor::Cursor, action: &text_editor::Action) {
+        for doc in self.documents.iter_mut().filter(|doc| doc.path == *path) {
+            let own = doc.content.cursor();
+            doc.content.move_to(cursor);
+            doc.history.record(&doc.content, action);
+            doc.content.perform(action.clone());
+            let position = clamp_position(&doc.content, own.position);
+            doc.content.move_to(text_editor::Cursor { position, selection: None });
+            doc.dirty = true;
+            doc.refresh_outline();
+        }
+    }
+
+    // Replace the text of a document which was undone, redone or saved in
+    // another pane, the replacement is a single undo step
+    pub fn sync_document(&mut self, path: &PathBuf, text: &str, dirty: bool) {
+        for doc in self.documents.iter_mut().filter(|doc| doc.path == *path) {
+            if doc.content.text() != text {
+                let cursor = doc.content.cursor();
+                doc.history.checkpoint(&doc.content);
+                doc.content = text_editor::Content::with_text(text);
+                let position = clamp_position(&doc.content, cursor.position);
+                doc.content.move_to(text_editor::Cursor { position, selection: None });
+                doc.refresh_outline();
+            }
+            doc.dirty = dirty;
+        }
+    }
+
+    // Closes every tab, returns false if the user cancelled closing one
+    pub fn close_all(&mut self, typst: &TypstContext) -> bool {
+        while !self.documents.is_empty() {
+            let count = self.documents.len();
+            self.close(count - 1, typst);
+            if self.documents.len() == count {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Moves the cursor to a heading and scrolls the preview to where it was rendered
+    fn jump_to_heading(&mut self, index: usize) -> Task<Message> {
+        let Some(doc) = self.active_document_mut() else {
+            return Task::none();
+        };
+        let Some(heading) = doc.headings.get(index) else {
+            return Task::none();
+        };
+        let position = clamp_position(&doc.content, text_editor::Position { line: heading.line, column: 0 });
+        doc.content.move_to(text_editor::Cursor { position, selection: None });
+
+        // Positions are matched to headings in order, so they can be off for
+        // headings which come from other files
+        let Some(heading_position) = doc.heading_positions.get(index) else {
+            return Task::none();
+        };
+        let (page, y) = (heading_position.page.saturating_sub(1), heading_position.y);
+        self.scroll_to_page(page, y)
+    }
+
+    // Files in the vault's typst directory open as notes, anything else is
+    // handed to the system
+    fn open_linked_file(&self, path: &str, absolute: bool) -> Task<Message> {
+        let Some(doc) = self.active_document() else {
+            return Task::none();
+        };
+        let file = if absolute {
+            rename::normalize(Path::new(path))
+        } else {
+            rename::resolve_path(&doc.path, path)
+        };
+        let in_vault = file.ancestors().any(|dir| {
+            dir.file_name().is_some_and(|name| name == "typst")
+                && dir.parent().is_some_and(|parent| parent.join("pdf").is_dir())
+        });
+        if in_vault && file.extension().is_some_and(|ext| ext == "typ") && file.is_file() {
+            return Task::done(Message::OpenFile(file));
+        }
+        open_external(&file.to_string_lossy());
+        Task::none()
+    }
+
+    // Forward search, scrolls the preview to the part of the document the
+    // cursor is in. It only scrolls when the cursor moves into another
+    // marked part so the preview can still be scrolled by hand.
+    fn sync_preview(&mut self) -> Task<Message> {
+        let Some(doc) = self.active_document_mut() else {
+            return Task::none();
+        };
+        let line = doc.content.cursor().position.line;
+        let Some(mark) = sync::mark_for_line(&doc.source_marks(), line) else {
+            return Task::none();
+        };
+        if doc.synced_line == Some(mark.line) {
+            return Task::none();
+        }
+        doc.synced_line = Some(mark.line);
+        self.scroll_to_page(mark.page.saturating_sub(1), mark.y)
+    }
+
+    // Inverse search, moves the cursor to the source of the part of the
+    // preview that was clicked
+    fn sync_editor(&mut self) {
+        let Some((page, point)) = self.hover else {
+            return;
+        };
+        let Some(doc) = self.active_document_mut() else {
+            return;
+        };
+        let Some(mark) = sync::mark_for_point(&doc.source_marks(), page + 1, point.y) else {
+            return;
+        };
+        let position = clamp_position(&doc.content, text_editor::Position { line: mark.line, column: 0 });
+        doc.content.move_to(text_editor::Cursor { position, selection: None });
+        doc.synced_line = Some(mark.line);
+    }
+
+    // Scrolls the preview to a point on a page, y is in points from the top of the page
+    fn scroll_to_page(&self, index: usize, y: f32) -> Task<Message> {
+        let Some(doc) = self.active_document() else {
+            return Task::none();
+        };
+        let Some(page) = doc.pages.get(index.min(doc.pages.len().saturating_sub(1))) else {
+            return Task::none();
+        };
+        let index = index.min(doc.pages.len() - 1);
+        match self.preview_viewport {
+            Some(viewport) => {
+                let size = viewport.bounds().size();
+                let top = doc.page_offsets(size)[index];
+                let y = top + y * doc.page_scale(page, size);
+                widget::operation::scroll_to(self.preview_id.clone(), AbsoluteOffset { x: None, y: Some(y) })
+            }
+            // Before the preview has scrolled we don't know its size, so
+            // assume pages are the same size
+            None => {
+                let total: f32 = doc.pages.iter().map(|page| page.height).sum();
+                if total <= 0.0 {
+                    return Task::none();
+                }
+                let above: f32 = doc.pages.iter().take(index).map(|page| page.height).sum();
+                widget::operation::snap_to(self.preview_id.clone(), RelativeOffset { x: 0.0, y: (above + y) / total })
+            }
+        }
+    }
+
+    // The page at the top of the preview and how far down it is, in points
+    fn preview_anchor(&self) -> Option<(usize, f32)> {
+        let doc = self.active_document()?;
+        let viewport = self.preview_viewport?;
+        let size = viewport.bounds().size();
+        let top = viewport.absolute_offset().y;
+        let offsets = doc.page_offsets(size);
+        let index = offsets.iter().rposition(|offset| *offset <= top)?;
+        let scale = doc.page_scale(&doc.pages[index], size);
+        Some((index, (top - offsets[index]) / scale))
+    }
+
+    // The page at the top third of the preview
+    fn current_page(&self) -> Option<usize> {
+        let doc = self.active_document()?;
+        if doc.pages.is_empty() {
+            return None;
+        }
+        let Some(viewport) = self.preview_viewport else {
+            return Some(0);
+        };
+        let bounds = viewport.bounds();
+        let line = viewport.absolute_offset().y + bounds.height / 3.0;
+        let offsets = doc.page_offsets(bounds.size());
+        Some(offsets.iter().rposition(|top| *top <= line).unwrap_or(0))
+    }
+
+    fn select(&mut self, index: usize) -> Task<Message> {
+        if index >= self.documents.len() {
+            return Task::none();
+        }
+        self.active = Some(index);
+        widget::operation::snap_to(self.preview_id.clone(), self.documents[index].preview_offset)
+    }
+
+    fn close(&mut self, index: usize, typst: &TypstContext) {
+        let Some(doc) = self.documents.get_mut(index) else {
+            return;
+        };
+
+        if doc.dirty {
+            let answer = MessageDialog::new()
+                .set_title("Unsaved changes")
+                .set_description(format!("Save changes to {}?", doc.title()))
+                .set_buttons(MessageButtons::YesNoCancel)
+                .show();
+            match answer {
+                MessageDialogResult::Yes => {
+                    if doc.save().is_err() {
+                        return;
+                    }
+                }
+                MessageDialogResult::No => {}
+                _ => return,
+            }
+        }
+
+        let mut doc = self.documents.remove(index);
+        doc.history.leave(&doc.content);
+        typst.clear_preview(doc.id);
+        self.zooms.insert(doc.path.clone(), doc.zoom);
+        self.histories.insert(doc.path, doc.history);
+
+        self.active = match self.active {
+            _ if self.documents.is_empty() => None,
+            Some(active) if active > index => Some(active - 1),
+            Some(active) => Some(active.min(self.documents.len() - 1)),
+            None => None,
+        };
+    }
+
+    // Re-read any open files which were changed on disk by something other
+    // than the editor, keeping the cursor as close to where it was as possible.
+    // The reload is a single undo step.
+    pub fn reload_files(&mut self, files: &[PathBuf], typst: &TypstContext) -> Task<Message> {
+        let mut tasks = vec!();
+        // Unsaved edits are kept, callers check for them before writing files
+        for doc in self.documents.iter_mut().filter(|doc| files.contains(&doc.path) && !doc.dirty) {
+            let Ok(text) = fs::read_to_string(&doc.path) else {
+                continue;
+            };
+
+            let cursor = doc.content.cursor();
+            doc.history.checkpoint(&doc.content);
+            doc.content = text_editor::Content::with_text(&text);
+            let position = clamp_position(&doc.content, cursor.position);
+            doc.content.move_to(text_editor::Cursor { position, selection: None });
+            doc.dirty = false;
+            doc.refresh_outline();
+
+            doc.next_render = Instant::now();
+            tasks.push(doc.render_task(typst));
         }
+        Task::batch(tasks)
     }
 
-    fn editor_view(&self) -> Element<'_, Message> {
+    fn editor_view<'a>(&'a self, doc: &'a Document) -> Element<'a, Message> {
         container(
-            text_editor(&self.content)
+            text_editor(&doc.content)
                 .placeholder("")
                 .on_action(Message::Edit)
+                .key_binding(key_binding)
+                .highlight_with::<TypstHighlighter>((), highlighter::to_format)
                 .style(|theme: &Theme, _status| {
                     let palette = theme.extended_palette();
                     text_editor::Style {
                         background: Background::Color(palette.background.base.color),
-                        border: Border { 
+                        border: Border {
                             radius: 1.0.into(),
                             width: 0.0,
                             color: palette.background.strong.color,
@@ -124,32 +800,144 @@ impl ContentArea {
     }
 
     // TODO error handling
-    fn preview_view(&self) -> Element<'_, Message> {
-        let mut svgs = column![].clip(false);
-        for file in self.preview_files.iter() {
-            let image = svg(&file);
-            svgs = svgs.push(image);
+    fn preview_view<'a>(&'a self, doc: &'a Document, backlinks: &'a [Backlink]) -> Element<'a, Message> {
+        let mut pages = Row::new().height(Length::Fill);
+        if self.thumbnails_open {
+            let border: Element<'a, Message> = Element::from(components::left_border(Color::BLACK));
+            pages = pages.push(self.thumbnails_view(doc)).push(border);
         }
-        container( scrollable( svgs ))
+        pages = pages.push(self.pages_view(doc));
+
+        column![
+            self.page_bar(doc),
+            hrule(),
+            pages,
+            hrule(),
+            backlinks_view(backlinks),
+        ]
         .width(Length::FillPortion(1))
         .height(Length::Fill)
-        .style(|_| container::Style {
-            background: Some(Background::Color(Color {r:0.9, g: 0.9, b: 0.9, a: 1.0})),
+        .into()
+    }
+
+    // Page N of M with controls to move between pages
+    fn page_bar<'a>(&'a self, doc: &'a Document) -> Element<'a, Message> {
+        let count = doc.pages.len();
+        let current = self.current_page().map(|page| page + 1).unwrap_or(0);
+        let has_pages = count > 0;
+        row![
+            button("◀").style(button::text).on_press_maybe((current > 1).then_some(Message::PreviousPage)),
+            text("Page"),
+            text_input(&current.to_string(), &self.page_input)
+                .on_input(Message::PageInput)
+                .on_submit(Message::SubmitPage)
+                .width(Length::Fixed(48.0)),
+            text(format!("of {}", count)),
+            button("▶").style(button::text).on_press_maybe((current < count).then_some(Message::NextPage)),
+            Space::new().width(Length::Fill),
+            button("Thumbnails").on_press_maybe(has_pages.then_some(Message::ToggleThumbnails)),
+        ]
+        .spacing(styles::SPACING_SMALL)
+        .padding(Padding::new(4.0).left(styles::SPACING_SMALL).right(styles::SPACING_SMALL))
+        .align_y(Alignment::Center)
+        .into()
+    }
+
+    fn thumbnails_view<'a>(&'a self, doc: &'a Document) -> Element<'a, Message> {
+        let current = self.current_page();
+        let mut thumbnails = column![].spacing(styles::SPACING_SMALL).padding(styles::SPACING_SMALL);
+        for (index, page) in doc.pages.iter().enumerate() {
+            let is_current = current == Some(index);
+            thumbnails = thumbnails.push(
+                mouse_area(
+                    column![
+                        container(page_image(page, THUMBNAIL_WIDTH, page.height * THUMBNAIL_WIDTH / page.width))
+                            .padding(2)
+                            .style(move |theme: &Theme| container::Style {
+                                border: Border {
+                                    width: if is_current { 2.0 } else { 1.0 },
+                                    color: if is_current { theme.palette().primary } else { Color::from_rgb(0.7, 0.7, 0.7) },
+                                    ..Border::default()
+                                },
+                                ..container::Style::default()
+                            }),
+                        text(index + 1).size(12),
+                    ]
+                    .align_x(Alignment::Center)
+                )
+                .on_press(Message::GoToPage(index))
+                .interaction(Interaction::Pointer)
+            );
+        }
+        scrollable(thumbnails)
+            .height(Length::Fill)
+            .into()
+    }
+
+    fn pages_view<'a>(&'a self, doc: &'a Document) -> Element<'a, Message> {
+        let hovered_page = self.hovered_link().map(|link| link.page - 1);
+        let zooming = self.modifiers.command();
+        container(responsive(move |size| {
+            let mut svgs = column![]
+                .spacing(PAGE_GAP)
+                .padding(PAGE_GAP)
+                .align_x(Alignment::Center)
+                .clip(false);
+            for (index, page) in doc.pages.iter().enumerate() {
+                // Mouse positions are converted back to points for hit testing
+                let scale = doc.page_scale(page, size);
+                let image = page_image(page, page.width * scale, page.height * scale);
+                let interaction = if hovered_page == Some(index) { Interaction::Pointer } else { Interaction::Idle };
+                let mut page_area = mouse_area(image)
+                    .on_move(move |point| Message::PreviewHovered(index, Point::new(point.x / scale, point.y / scale)))
+                    .on_press(Message::PreviewClicked)
+                    .interaction(interaction);
+                // Only listen for the wheel while zooming so it still scrolls
+                if zooming {
+                    page_area = page_area.on_scroll(Message::PreviewWheel);
+                }
+                svgs = svgs.push(page_area);
+            }
+            scrollable(svgs)
+                .id(self.preview_id.clone())
+                .direction(scrollable::Direction::Both {
+                    vertical: scrollable::Scrollbar::default(),
+                    horizontal: scrollable::Scrollbar::default(),
+                })
+                .on_scroll(Message::PreviewScrolled)
+                .into()
+        }))
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .style(|theme: &Theme| container::Style {
+            background: Some(Background::Color(theme.extended_palette().background.weak.color)),
             ..container::Style::default()
         })
         .into()
     }
 
-    pub fn view(&self) -> Element<'_, Message> {
+    // Backlinks are looked up by the Layout since it owns the link index
+    pub fn view<'a>(&'a self, backlinks: &'a [Backlink]) -> Element<'a, Message> {
         let mut container = Row::new()
                 .width(Length::Fill);
 
+        let Some(doc) = self.active_document() else {
+            return container
+                .push(text("No file open"))
+                .height(Length::Fill)
+                .into();
+        };
+
+        if self.outline_open {
+            container = container.push(outline_view(doc));
+        }
+
         if self.editor_open {
-            container = container.push(self.editor_view());
+            container = container.push(self.editor_view(doc));
         }
 
         if self.preview_open {
-            container = container.push(self.preview_view());
+            container = container.push(self.preview_view(doc, backlinks));
         }
 
         container
@@ -157,3 +945,265 @@ impl ContentArea {
             .into()
     }
 }
+
+impl Document {
+    pub fn title(&self) -> String {
+        self.path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn text(&self) -> String {
+        self.content.text()
+    }
+
+    pub fn cursor(&self) -> text_editor::Cursor {
+        self.content.cursor()
+    }
+
+    // Pixels per point for a page in a preview of the given size
+    fn page_scale(&self, page: &PreviewPage, size: Size) -> f32 {
+        let fit_width = (size.width - 2.0 * PAGE_GAP).max(1.0) / page.width;
+        match self.zoom {
+            Zoom::FitWidth => fit_width,
+            Zoom::FitPage => fit_width.min((size.height - 2.0 * PAGE_GAP).max(1.0) / page.height),
+            Zoom::Scale(scale) => scale,
+        }
+    }
+
+    // Where each page starts in the preview, in pixels, laid out the same
+    // way as in pages_view
+    fn page_offsets(&self, size: Size) -> Vec<f32> {
+        let mut top = PAGE_GAP;
+        self.pages.iter()
+            .map(|page| {
+                let offset = top;
+                top += page.height * self.page_scale(page, size) + PAGE_GAP;
+                offset
+            })
+            .collect()
+    }
+
+    // The scale of the first page, so zooming from a fit mode starts from what's shown
+    fn current_scale(&self, size: Option<Size>) -> f32 {
+        match (self.zoom, self.pages.first(), size) {
+            (Zoom::Scale(scale), _, _) => scale,
+            (_, Some(page), Some(size)) => self.page_scale(page, size),
+            _ => 1.0,
+        }
+    }
+
+    fn source_marks(&self) -> Vec<SourceMark> {
+        sync::source_marks(&self.line_marks, &self.headings, &self.heading_positions)
+    }
+
+    fn refresh_outline(&mut self) {
+        self.headings = outline::parse_headings(&self.content.text());
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn save(&mut self) -> Result<(), FileSystemError> {
+        fs::write(&self.path, self.content.text())
+            .map_err(|_| FileSystemError::WriteFileError { path: self.path.clone().into() })?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn render_task(&mut self, typst: &TypstContext) -> Task<Message> {
+        // Debounce
+        let now = Instant::now();
+        if now < self.next_render {
+            return Task::none();
+        }
+        else {
+            let next_render = now.checked_add(Duration::from_secs(SECONDS_BETWEEN_RENDER)).unwrap();
+            self.next_render = next_render;
+        }
+
+        // Render, then find where links, headings and paragraphs ended up so
+        // the preview can be clicked and kept in step with the editor
+        let id = self.id;
+        let preview_path = typst.preview_path(id);
+        let mut content = links::preprocess(&sync::add_line_markers(&self.content.text()));
+        if typst.dark {
+            content.insert_str(0, links::DARK_PRELUDE);
+        }
+        let open_file = self.path.clone();
+        let raster_ppi = typst.raster_fallback.then_some((self.raster_scale * 72.0).clamp(MIN_PPI, MAX_PPI));
+        Task::perform(
+            async move {
+                TypstContext::compile(preview_path.clone(), content.clone(), open_file.clone()).await?;
+                // Heavy pages are rendered again as PNGs, the rest of the
+                // preview doesn't wait on it failing
+                let mut raster_pages = vec!();
+                if let (Some(ppi), Some(dir)) = (raster_ppi, preview_path.parent()) {
+                    let heavy = typst::heavy_pages(&typst::preview_files(dir).unwrap_or_default());
+                    if TypstContext::compile_png(preview_path, content.clone(), open_file.clone(), heavy.clone(), ppi).await.is_ok() {
+                        raster_pages = heavy;
+                    }
+                }
+                // One query finds everything, it's split up by label after
+                let selector = typst::any_label(&[links::LINK_LABEL, outline::HEADING_LABEL, sync::LINE_LABEL]);
+                let values = TypstContext::query_elements(content, open_file, selector).await
+                    .map(|output| typst::values_by_label(&output))
+                    .unwrap_or_default();
+                let values_for = |label: &str| values.get(label).map(String::as_str).unwrap_or("[]");
+                let links = links::parse_link_regions(values_for(links::LINK_LABEL));
+                let heading_positions = outline::parse_heading_positions(values_for(outline::HEADING_LABEL));
+                let line_marks = sync::parse_marks(values_for(sync::LINE_LABEL));
+                Ok(RenderOutput { links, heading_positions, line_marks, raster_pages, raster_ppi: raster_ppi.unwrap_or_default() })
+            },
+            move |result| Message::RenderDone(id, result)
+        )
+    }
+}
+
+// The headings of the open note, nested by level with the section the
+// cursor is in highlighted
+fn outline_view(doc: &Document) -> Element<'_, Message> {
+    let current = outline::current_heading(&doc.headings, doc.content.cursor().position.line);
+    let mut list = column![].spacing(2);
+    for (index, heading) in doc.headings.iter().enumerate() {
+        let is_current = current == Some(index);
+        list = list.push(
+            mouse_area(
+                container(text(&heading.text).size(13))
+                    .width(Length::Fill)
+                    .padding(Padding::ZERO.left(12.0 * (heading.level - 1) as f32))
+                    .style(move |theme: &Theme| {
+                        let palette = theme.extended_palette();
+                        container::Style {
+                            background: is_current.then_some(Background::Color(palette.primary.weak.color)),
+                            text_color: is_current.then_some(palette.primary.weak.text),
+                            ..container::Style::default()
+                        }
+                    })
+            )
+            .on_press(Message::JumpToHeading(index))
+            .interaction(Interaction::Pointer)
+        );
+    }
+
+    container(scrollable(list.padding(styles::SPACING_SMALL)))
+        .width(Length::Fixed(200.0))
+        .height(Length::Fill)
+        .into()
+}
+
+// The notes linking to the open one, each with the line the link is on
+fn backlinks_view(backlinks: &[Backlink]) -> Element<'_, Message> {
+    let mut list = column![
+        text(format!("Backlinks ({})", backlinks.len())).size(14),
+    ]
+    .spacing(styles::SPACING_SMALL / 2.0)
+    .padding(styles::SPACING_SMALL);
+
+    for backlink in backlinks {
+        let title = backlink.source.file_stem()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        list = list.push(
+            mouse_area(
+                column![
+                    text(title).size(13),
+                    text(&backlink.context).size(12),
+                ]
+                .width(Length::Fill)
+            )
+            .on_press(Message::OpenFileAt(backlink.source.clone(), backlink.line, 0))
+            .interaction(Interaction::Pointer)
+        );
+    }
+
+    scrollable(list)
+        .width(Length::Fill)
+        .height(Length::Shrink)
+        .into()
+}
+
+// Pages are hashed so any page matching one from the last render, even if
+// it moved, keeps its handle and isn't parsed or drawn again. Rasters are
+// keyed by one based page number.
+fn preview_pages(files: Vec<PathBuf>, previous: &[PreviewPage], rasters: &HashMap<usize, PathBuf>, ppi: f32) -> Vec<PreviewPage> {
+    let previous: HashMap<u64, &PreviewPage> = previous.iter()
+        .map(|page| (page.hash, page))
+        .collect();
+    files.into_iter().map(|path| {
+        let contents = fs::read(&path).unwrap_or_default();
+        let mut hasher = DefaultHasher::new();
+        contents.hash(&mut hasher);
+        let hash = hasher.finish();
+        let previous = previous.get(&hash);
+
+        let raster = match (typst::page_number(&path).and_then(|number| rasters.get(&number)), previous) {
+            (None, _) => None,
+            (Some(_), Some(page)) if page.raster.is_some() && page.raster_ppi == ppi => page.raster.clone(),
+            (Some(raster), _) => fs::read(raster).ok().map(image::Handle::from_bytes),
+        };
+        if let Some(page) = previous {
+            return PreviewPage { path, raster, raster_ppi: ppi, ..(*page).clone() };
+        }
+
+        // A4 in points if the size can't be read
+        let (width, height) = std::str::from_utf8(&contents).ok()
+            .and_then(typst::svg_size)
+            .unwrap_or((595.0, 842.0));
+        PreviewPage { path, handle: svg::Handle::from_memory(contents), hash, raster, raster_ppi: ppi, width, height }
+    })
+    .collect()
+}
+
+fn page_image(page: &PreviewPage, width: f32, height: f32) -> Element<'_, Message> {
+    match &page.raster {
+        Some(raster) => image(raster.clone()).width(width).height(height).into(),
+        None => svg(page.handle.clone()).width(width).height(height).into(),
+    }
+}
+
+// Links leave the app, so ask first
+fn open_external(target: &str) {
+    let answer = MessageDialog::new()
+        .set_title("Open link")
+        .set_description(format!("Open '{}' outside of memristor?", target))
+        .set_buttons(MessageButtons::YesNo)
+        .show();
+    if answer == MessageDialogResult::Yes && let Err(err) = links::open_external(target) {
+        MessageDialog::new()
+            .set_title("Couldn't open link")
+            .set_description(err.to_string())
+            .set_level(rfd::MessageLevel::Error)
+            .set_buttons(MessageButtons::Ok)
+            .show();
+    }
+}
+
+// Undo, redo and save on top of the default editor bindings
+fn key_binding(key_press: text_editor::KeyPress) -> Option<text_editor::Binding<Message>> {
+    let focused = matches!(key_press.status, text_editor::Status::Focused { .. });
+    let modifiers = key_press.modifiers;
+    if focused && modifiers.command() {
+        match key_press.key.to_latin(key_press.physical_key).map(|c| c.to_ascii_lowercase()) {
+            Some('z') if modifiers.shift() => return Some(text_editor::Binding::Custom(Message::Redo)),
+            Some('z') => return Some(text_editor::Binding::Custom(Message::Undo)),
+            Some('y') => return Some(text_editor::Binding::Custom(Message::Redo)),
+            Some('s') => return Some(text_editor::Binding::Custom(Message::Save)),
+            _ => {}
+        }
+    }
+    text_editor::Binding::from_key_press(key_press)
+}
+
+// Keep a position inside the content, lines can disappear when a file is
+// reloaded from disk
+pub fn clamp_position(content: &text_editor::Content, position: text_editor::Position) -> text_editor::Position {
+    let line = position.line.min(content.line_count().saturating_sub(1));
+    let line_text = content.line(line).map(|line| line.text.into_owned()).unwrap_or_default();
+    let mut column = position.column.min(line_text.len());
+    while !line_text.is_char_boundary(column) {
+        column -= 1;
+    }
+    text_editor::Position { line, column }
+}
@@ -0,0 +1,371 @@
+#![allow(dead_code, unused)]
+
+// Daily notes live in one folder with a note per date, named with the
+// format from the settings. The calendar marks the dates which have a note
+// and opens or creates the note for a date when it's clicked.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use iced::{Element, Length, Padding};
+use iced::widget::{button, column, row, text, Column, Row};
+
+use crate::error::FileSystemError;
+use crate::links;
+use crate::settings::Settings;
+use crate::styles;
+use crate::templates::{self, Placeholders};
+
+const DEFAULT_TEMPLATE: &str = "= {{date}}\n\n";
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+const WEEKDAYS: [&str; 7] = ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: i32,
+    // One based
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    // In local time, days change over at local midnight
+    pub fn today() -> Self {
+        local_now().0
+    }
+
+    // Days since 1970-01-01, from Howard Hinnant's civil calendar algorithms
+    pub fn from_days(days: i64) -> Self {
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = (yoe + era * 400 + if month <= 2 { 1 } else { 0 }) as i32;
+        Date { year, month, day }
+    }
+
+    pub fn to_days(self) -> i64 {
+        let year = self.year as i64 - if self.month <= 2 { 1 } else { 0 };
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let month = self.month as i64;
+        let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146_097 + doe - 719_468
+    }
+
+    pub fn add_days(self, days: i64) -> Self {
+        Date::from_days(self.to_days() + days)
+    }
+
+    // Zero for Monday
+    pub fn weekday(self) -> u32 {
+        (self.to_days() + 3).rem_euclid(7) as u32
+    }
+
+    pub fn format(self, format: &str) -> String {
+        format
+            .replace("YYYY", &format!("{:04}", self.year))
+            .replace("MM", &format!("{:02}", self.month))
+            .replace("DD", &format!("{:02}", self.day))
+    }
+
+    // The inverse of format, None if the name doesn't match or isn't a real date
+    pub fn parse(name: &str, format: &str) -> Option<Self> {
+        let (mut year, mut month, mut day) = (None, None, None);
+        let mut name = name;
+        let mut format = format;
+        while !format.is_empty() {
+            let (token, width) = if format.starts_with("YYYY") {
+                (Some(&mut year), 4)
+            } else if format.starts_with("MM") {
+                (Some(&mut month), 2)
+            } else if format.starts_with("DD") {
+                (Some(&mut day), 2)
+            } else {
+                (None, 1)
+            };
+            match token {
+                Some(value) => {
+                    let digits = name.get(..width)?;
+                    if !digits.chars().all(|c| c.is_ascii_digit()) {
+                        return None;
+                    }
+                    *value = Some(digits.parse::<u32>().ok()?);
+                    name = &name[width..];
+                    format = &format[width..];
+                }
+                None => {
+                    let c = format.chars().next()?;
+                    name = name.strip_prefix(c)?;
+                    format = &format[c.len_utf8()..];
+                }
+            }
+        }
+        if !name.is_empty() {
+            return None;
+        }
+
+        let date = Date { year: year? as i32, month: month?, day: day? };
+        (date.month >= 1 && date.month <= 12 && date.day >= 1 && date.day <= days_in_month(date.year, date.month))
+            .then_some(date)
+    }
+}
+
+// The local date and the time of day in minutes. The C library's localtime
+// does the timezone work, if it fails this falls back to UTC.
+pub fn local_now() -> (Date, u32) {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let time = seconds as libc::time_t;
+    // SAFETY: tm is plain data and localtime only writes to the one given
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    #[cfg(windows)]
+    let converted = unsafe { libc::localtime_s(&mut tm, &time) == 0 };
+    #[cfg(not(windows))]
+    let converted = unsafe { !libc::localtime_r(&time, &mut tm).is_null() };
+
+    if !converted {
+        let minutes = (seconds / 60 % 1440) as u32;
+        return (Date::from_days((seconds / 86_400) as i64), minutes);
+    }
+    let date = Date { year: tm.tm_year + 1900, month: tm.tm_mon as u32 + 1, day: tm.tm_mday as u32 };
+    (date, tm.tm_hour as u32 * 60 + tm.tm_min as u32)
+}
+
+pub fn days_in_month(year: i32, month: u32) -> u32 {
+    let first = Date { year, month, day: 1 };
+    let next = if month == 12 { Date { year: year + 1, month: 1, day: 1 } } else { Date { year, month: month + 1, day: 1 } };
+    (next.to_days() - first.to_days()) as u32
+}
+
+pub struct Calendar {
+    // The month being shown
+    year: i32,
+    month: u32,
+    // Dates which already have a note
+    existing: BTreeSet<Date>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    PreviousMonth,
+    NextMonth,
+    // These are handled at the Layout level since they open files
+    Open(Date),
+    Today,
+    PreviousDay,
+    NextDay,
+}
+
+impl Calendar {
+    pub fn new() -> Self {
+        let today = Date::today();
+        Calendar {
+            year: today.year,
+            month: today.month,
+            existing: BTreeSet::new(),
+        }
+    }
+
+    pub fn rebuild(&mut self, settings: &Settings) {
+        self.existing = existing_dates(settings);
+    }
+
+    pub fn show_month(&mut self, date: Date) {
+        self.year = date.year;
+        self.month = date.month;
+    }
+
+    pub fn update(&mut self, message: Message) {
+        match message {
+            Message::PreviousMonth => {
+                let first = Date { year: self.year, month: self.month, day: 1 };
+                self.show_month(first.add_days(-1));
+            }
+            Message::NextMonth => {
+                let first = Date { year: self.year, month: self.month, day: 1 };
+                self.show_month(first.add_days(days_in_month(self.year, self.month) as i64));
+            }
+            Message::Open(_) | Message::Today | Message::PreviousDay | Message::NextDay => {
+                unreachable!("Handled in layout.rs")
+            }
+        }
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let today = Date::today();
+        let first = Date { year: self.year, month: self.month, day: 1 };
+
+        let mut weeks = Column::new().spacing(2);
+        let mut week = Row::new().spacing(2);
+        for label in WEEKDAYS {
+            week = week.push(text(label).size(12).width(Length::Fill));
+        }
+        weeks = weeks.push(week);
+
+        // Pad the first week so days line up under their weekday
+        week = Row::new().spacing(2);
+        for _ in 0..first.weekday() {
+            week = week.push(text("").width(Length::Fill));
+        }
+        for day in 1..=days_in_month(self.year, self.month) {
+            let date = Date { year: self.year, month: self.month, day };
+            let style = if self.existing.contains(&date) {
+                button::primary
+            } else if date == today {
+                button::secondary
+            } else {
+                button::text
+            };
+            week = week.push(
+                button(text(day).size(12))
+                    .padding(Padding::new(2.0))
+                    .width(Length::Fill)
+                    .style(style)
+                    .on_press(Message::Open(date))
+            );
+            if date.weekday() == 6 {
+                weeks = weeks.push(week);
+                week = Row::new().spacing(2);
+            }
+        }
+        // Fill out the last week so its days are as wide as the others
+        let last = Date { year: self.year, month: self.month, day: days_in_month(self.year, self.month) };
+        if last.weekday() != 6 {
+            for _ in last.weekday() + 1..7 {
+                week = week.push(text("").width(Length::Fill));
+            }
+            weeks = weeks.push(week);
+        }
+
+        column![
+            row![
+                button("<").style(button::text).on_press(Message::PreviousMonth),
+                text(format!("{} {}", MONTHS[self.month as usize - 1], self.year)).width(Length::Fill).center(),
+                button(">").style(button::text).on_press(Message::NextMonth),
+            ]
+            .align_y(iced::Alignment::Center),
+            weeks,
+            row![
+                button("Previous day").on_press(Message::PreviousDay),
+                button("Today").on_press(Message::Today),
+                button("Next day").on_press(Message::NextDay),
+            ]
+            .spacing(styles::SPACING_SMALL),
+        ]
+        .spacing(styles::SPACING_SMALL)
+        .padding(Padding::new(styles::SPACING_SMALL))
+        .into()
+    }
+}
+
+/////////// Logic ///////////////////
+
+pub fn note_path(settings: &Settings, date: Date) -> Option<PathBuf> {
+    let root = settings.root_dir.as_ref()?;
+    let target = format!("{}/{}", settings.daily_dir().trim_matches('/'), date.format(settings.daily_format()));
+    links::resolve(Path::new(root), &target)
+}
+
+// The date of a daily note, None for notes outside the daily folder
+pub fn note_date(settings: &Settings, path: &Path) -> Option<Date> {
+    let root = settings.root_dir.as_ref()?;
+    let dir = Path::new(root).join("typst").join(settings.daily_dir().trim_matches('/'));
+    // Formats can have dots in them so only .typ is taken off
+    let name = path.strip_prefix(&dir).ok()?.to_string_lossy().replace('\\', "/");
+    Date::parse(name.strip_suffix(".typ")?, settings.daily_format())
+}
+
+fn existing_dates(settings: &Settings) -> BTreeSet<Date> {
+    let Some(root) = settings.root_dir.as_ref() else {
+        return BTreeSet::new();
+    };
+    let dir = Path::new(root).join("typst").join(settings.daily_dir().trim_matches('/'));
+    // The format may put notes in nested folders
+    let mut dates = BTreeSet::new();
+    let mut dirs = vec![dir];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if let Some(date) = note_date(settings, &path) {
+                dates.insert(date);
+            }
+        }
+    }
+    dates
+}
+
+// Creates the note for a date from the template in the settings, or a
+// heading with the date if there isn't one. The title and date placeholders
+// are the note's date rather than today's.
+pub fn create_note(settings: &Settings, path: &Path, date: Date) -> Result<Option<(usize, usize)>, FileSystemError> {
+    let template = match (&settings.root_dir, &settings.daily_template) {
+        (Some(root), Some(template)) => templates::read_template(Path::new(root), template)
+            // Before templates had their own folder this was a path in the typst folder
+            .or_else(|err| {
+                fs::read_to_string(Path::new(root).join("typst").join(template.trim_start_matches('/'))).map_err(|_| err)
+            })?,
+        _ => DEFAULT_TEMPLATE.to_string(),
+    };
+    let date = date.format("YYYY-MM-DD");
+    let placeholders = Placeholders {
+        title: date.clone(),
+        date,
+        ..Placeholders::now("", settings)
+    };
+    let (text, cursor) = templates::fill(&template, &placeholders);
+    links::create_note(path)?;
+    fs::write(path, text)
+        .map_err(|_| FileSystemError::WriteFileError { path: path.into() })?;
+    Ok(cursor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_convert_and_parse() {
+        assert_eq!(Date::from_days(0), Date { year: 1970, month: 1, day: 1 });
+        let leap = Date { year: 2024, month: 2, day: 29 };
+        assert_eq!(Date::from_days(leap.to_days()), leap);
+        assert_eq!(leap.add_days(1), Date { year: 2024, month: 3, day: 1 });
+        assert_eq!(leap.weekday(), 3);
+        assert_eq!(days_in_month(2023, 2), 28);
+
+        assert_eq!(leap.format("YYYY/MM-DD"), "2024/02-29");
+        assert_eq!(Date::parse("2024/02-29", "YYYY/MM-DD"), Some(leap));
+        assert_eq!(Date::parse("2023-02-29", "YYYY-MM-DD"), None);
+        assert_eq!(Date::parse("2024-02-29 notes", "YYYY-MM-DD"), None);
+    }
+
+    #[test]
+    fn dotted_formats_are_not_extensions() {
+        let settings = Settings {
+            root_dir: Some("/vault".into()),
+            daily_format: Some("DD.MM.YYYY".into()),
+            ..Settings::default()
+        };
+        let date = Date { year: 2024, month: 2, day: 29 };
+        let path = note_path(&settings, date).unwrap();
+        assert_eq!(path, Path::new("/vault/typst/daily/29.02.2024.typ"));
+        assert_eq!(note_date(&settings, &path), Some(date));
+    }
+}
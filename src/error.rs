@@ -20,6 +20,21 @@ pub enum FileSystemError {
     #[error("Could not create dir")]
     CreateDirError { path: OsString },
 
+    #[error("Could not write file")]
+    WriteFileError { path: OsString },
+
+    #[error("Could not rename file: '{path:?}'")]
+    RenameError { path: OsString },
+
+    #[error("File already exists: '{path:?}'")]
+    FileExistsError { path: OsString },
+
+    #[error("Path is outside of the vault: '{path:?}'")]
+    OutsideVaultError { path: OsString },
+
+    #[error("File changed on disk: '{path:?}'")]
+    FileChangedError { path: OsString },
+
     #[error("Not Memristor Directory")]
     NotMemristerDirectory,
 
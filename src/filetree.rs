@@ -1,26 +1,49 @@
 #![allow(dead_code, unused)]
 
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
 use iced::{Element, Padding, Length, Color};
-use iced::widget::{row, Column, text, mouse_area, container};
+use iced::widget::{row, column, Column, text, text_input, button, mouse_area, container, pick_list};
 
 use crate::settings::Settings;
 use crate::components;
 use crate::error::FileSystemError;
 use crate::styles;
+use crate::templates::TemplateChoice;
 
 pub struct FileTree {
     root: Option<FsDir>,
-    focus_path: Option<String>
+    focus_path: Option<String>,
+    typst_dir: Option<PathBuf>,
+    // The file being renamed and the path typed for it so far
+    renaming: Option<(PathBuf, String)>,
+    // When set only these files are shown, folders are expanded to show them
+    filter: Option<Vec<PathBuf>>,
+    // Shown instead of the file name for notes which have a title
+    titles: HashMap<PathBuf, String>,
+    // The path typed for a new note and the template it starts from
+    creating: Option<(String, TemplateChoice)>,
+    templates: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
     ToggleExpandDir(String),
     OpenFile(PathBuf),
+    StartRename(PathBuf),
+    RenameInput(String),
+    CancelRename,
+    // Handled in layout since other notes might need updating
+    SubmitRename,
+    StartNewNote,
+    NewNoteInput(String),
+    NewNoteTemplate(TemplateChoice),
+    CancelNewNote,
+    // Handled in layout since the note is opened once it's created
+    SubmitNewNote,
 }
 
 fn pathbuf_to_string<'a>(buf: &'a PathBuf) -> Cow<'a, str> {
@@ -43,6 +66,12 @@ impl<'a> FileTree {
         FileTree {
             root: init_dir,
             focus_path: None,
+            typst_dir: settings.root_dir.as_ref().map(|dir| Path::new(dir).join("typst")),
+            renaming: None,
+            filter: None,
+            titles: HashMap::new(),
+            creating: None,
+            templates: vec!(),
         }
     }
 
@@ -52,16 +81,80 @@ impl<'a> FileTree {
             Message::ToggleExpandDir(id) => {
                 self.root.as_mut().map(|fs_dir| fs_dir.toggle_expanded(id));
             },
-            Message::OpenFile(_) => { 
-                unreachable!("Should be handled in layout");
+            // Opening the file is handled in layout, we just track which file has focus
+            Message::OpenFile(path) => { 
+                self.focus_path = Some(path.to_string_lossy().to_string());
             },
+            // Files are renamed by typing a new path relative to the typst
+            // directory, so they can be moved between folders too
+            Message::StartRename(path) => {
+                let relative = self.typst_dir.as_ref()
+                    .and_then(|dir| path.strip_prefix(dir).ok())
+                    .map(|relative| relative.to_string_lossy().replace('\\', "/"))
+                    .unwrap_or_default();
+                self.renaming = Some((path, relative));
+            },
+            Message::RenameInput(input) => {
+                if let Some((_, current)) = self.renaming.as_mut() {
+                    *current = input;
+                }
+            },
+            Message::CancelRename => {
+                self.renaming = None;
+            },
+            Message::SubmitRename => { unreachable!("Handled in layout.rs") },
+            Message::StartNewNote => {
+                self.creating = Some((String::new(), TemplateChoice::FolderDefault));
+            },
+            Message::NewNoteInput(input) => {
+                if let Some((current, _)) = self.creating.as_mut() {
+                    *current = input;
+                }
+            },
+            Message::NewNoteTemplate(choice) => {
+                if let Some((_, current)) = self.creating.as_mut() {
+                    *current = choice;
+                }
+            },
+            Message::CancelNewNote => {
+                self.creating = None;
+            },
+            Message::SubmitNewNote => { unreachable!("Handled in layout.rs") },
         }
     }
 
+    pub fn set_templates(&mut self, templates: Vec<String>) {
+        self.templates = templates;
+    }
+
+    // The path typed for the new note and the template chosen for it
+    pub fn new_note_request(&self) -> Option<(String, TemplateChoice)> {
+        self.creating.clone()
+    }
+
+    pub fn set_titles(&mut self, titles: HashMap<PathBuf, String>) {
+        self.titles = titles;
+    }
+
+    pub fn set_filter(&mut self, filter: Option<Vec<PathBuf>>) {
+        self.filter = filter;
+    }
+
+    fn is_shown(&self, file: &PathBuf) -> bool {
+        self.filter.as_ref().is_none_or(|filter| filter.contains(file))
+    }
+
+    // The file being renamed and where it should go
+    pub fn rename_request(&self) -> Option<(PathBuf, String)> {
+        self.renaming.clone()
+    }
+
     pub fn open_dir(&mut self, dir: PathBuf, settings: &mut Settings) {
         match read_filesystem(&dir) {
             Ok(fs_dir) => {
                 self.root = Some(fs_dir);
+                self.typst_dir = Some(dir.join("typst"));
+                self.renaming = None;
                 settings.root_dir = Some(dir.to_string_lossy().to_string());
                 settings.write();
             }
@@ -71,6 +164,40 @@ impl<'a> FileTree {
     }
 
 
+    // Re-read the directory after files were added, keeping expanded folders open
+    pub fn refresh(&mut self, settings: &Settings) {
+        let Some(dir) = &settings.root_dir else {
+            return;
+        };
+        let Ok(mut fs_dir) = read_filesystem(Path::new(dir)) else {
+            return;
+        };
+        if let Some(old_root) = &self.root {
+            let mut expanded = vec!();
+            old_root.expanded_ids(&mut expanded);
+            for id in expanded {
+                fs_dir.toggle_expanded(id);
+            }
+        }
+        self.root = Some(fs_dir);
+    }
+
+    // Expanded folders by their path relative to the typst directory, unlike
+    // ids these stay the same when other folders are added
+    pub fn expanded_dirs(&self) -> Vec<String> {
+        let mut paths = vec!();
+        if let Some(root) = &self.root {
+            root.expanded_paths("", &mut paths);
+        }
+        paths
+    }
+
+    pub fn expand_dirs(&mut self, paths: &[String]) {
+        if let Some(root) = self.root.as_mut() {
+            root.expand_paths("", paths);
+        }
+    }
+
     // Impliment the view as a collection of nested Columns
     pub fn view(&self) -> Element<'_, Message> {
         // If the file dir isn't open then we just render a placeholder message for now
@@ -82,8 +209,12 @@ impl<'a> FileTree {
         }
         else {
             let root = self.root.as_ref().unwrap();
+            let new_note = match &self.creating {
+                Some((input, choice)) => self.render_new_note(input, choice),
+                None => button("New note").on_press(Message::StartNewNote).into(),
+            };
             let filetree = self.render_level(root);
-            container(filetree).align_top(Length::Fill)
+            container(column![new_note, filetree].spacing(styles::SPACING_SMALL)).align_top(Length::Fill)
         };
 
         row![
@@ -95,19 +226,47 @@ impl<'a> FileTree {
     }
 
 
+    fn render_new_note(&'a self, input: &'a str, choice: &TemplateChoice) -> Element<'a, Message> {
+        let mut choices = vec![TemplateChoice::FolderDefault, TemplateChoice::Empty];
+        choices.extend(self.templates.iter().cloned().map(TemplateChoice::Template));
+        column![
+            text_input("Path, e.g. folder/note", input)
+                .on_input(Message::NewNoteInput)
+                .on_submit(Message::SubmitNewNote),
+            row![
+                pick_list(choices, Some(choice.clone()), Message::NewNoteTemplate).width(Length::Fill),
+                button("Create").on_press(Message::SubmitNewNote),
+                button("×").on_press(Message::CancelNewNote),
+            ]
+            .spacing(5),
+        ]
+        .spacing(5)
+        .into()
+    }
+
     // TODO consider using keyed columns and the from_vecs method
-    fn render_level(&self, fs_dir: &'a FsDir) -> Column<'a, Message> {
+    fn render_level(&'a self, fs_dir: &'a FsDir) -> Column<'a, Message> {
         let mut col = Column::<'_, Message>::new();
         // Loop over the directories 
         for dir in fs_dir.dirs.iter() {
+            let filtering = self.filter.is_some();
+            if filtering && !dir.any_file(&|file| self.is_shown(file)) {
+                continue;
+            }
             col = col.push(render_dir_row(dir));
-            if dir.expanded {
+            if dir.expanded || filtering {
                 col = col.push(self.render_level(dir));
             }
         }
         // Loop over the files
         for (_file_count, file) in fs_dir.files.iter().enumerate() {
-            col = col.push(render_file_row(file));
+            if !self.is_shown(file) {
+                continue;
+            }
+            col = match &self.renaming {
+                Some((path, input)) if path == file => col.push(render_rename_row(input)),
+                _ => col.push(render_file_row(file, self.titles.get(file))),
+            };
         }
         col
         .padding(Padding::ZERO.left(20))
@@ -130,12 +289,28 @@ fn render_dir_row(fs_dir: &FsDir) -> Element<'_, Message> {
     .into()
 }
 
-fn render_file_row(file: &PathBuf) -> Element<'_, Message> {
-    let filename = PathBuf::from(file.as_path().file_name().unwrap());
+fn render_file_row<'a>(file: &'a Path, title: Option<&'a String>) -> Element<'a, Message> {
+    let filename = PathBuf::from(file.file_name().unwrap());
+    let label = match title {
+        Some(title) if !title.is_empty() => title.clone(),
+        _ => pathbuf_to_string(&filename).into_owned(),
+    };
     mouse_area(
-        text(pathbuf_to_string(&filename).into_owned())
+        text(label)
     )
     .on_press(Message::OpenFile(file.to_path_buf()))
+    .on_right_press(Message::StartRename(file.to_path_buf()))
+    .into()
+}
+
+fn render_rename_row(input: &str) -> Element<'_, Message> {
+    row![
+        text_input("New path", input)
+            .on_input(Message::RenameInput)
+            .on_submit(Message::SubmitRename),
+        button("×").on_press(Message::CancelRename),
+    ]
+    .spacing(5)
     .into()
 }
 
@@ -164,6 +339,37 @@ impl FsDir {
         }
     }
 
+    fn any_file(&self, predicate: &dyn Fn(&PathBuf) -> bool) -> bool {
+        self.files.iter().any(predicate) || self.dirs.iter().any(|dir| dir.any_file(predicate))
+    }
+
+    fn expanded_ids(&self, ids: &mut Vec<String>) {
+        if self.expanded {
+            ids.push(self.id.clone());
+        }
+        for dir in self.dirs.iter() {
+            dir.expanded_ids(ids);
+        }
+    }
+
+    fn expanded_paths(&self, prefix: &str, paths: &mut Vec<String>) {
+        for dir in self.dirs.iter() {
+            let path = format!("{}{}", prefix, dir.path.to_string_lossy());
+            if dir.expanded {
+                paths.push(path.clone());
+            }
+            dir.expanded_paths(&format!("{}/", path), paths);
+        }
+    }
+
+    fn expand_paths(&mut self, prefix: &str, paths: &[String]) {
+        for dir in self.dirs.iter_mut() {
+            let path = format!("{}{}", prefix, dir.path.to_string_lossy());
+            dir.expanded |= paths.contains(&path);
+            dir.expand_paths(&format!("{}/", path), paths);
+        }
+    }
+
     fn toggle_expanded(&mut self, id: String) {
         let mut dir_stack: Vec<&mut FsDir> = vec!(self);
         while !dir_stack.is_empty() {
@@ -239,6 +445,29 @@ pub fn read_filesystem(root_dir: &Path) -> Result<FsDir, FileSystemError> {
     read_directory(&typst_path, "", 0)
 }
 
+// Every .typ file under the vault's typst directory, sorted so that anything
+// built from the list (search results, indexes) has a stable order
+pub fn typst_files(root_dir: &Path) -> Result<Vec<PathBuf>, FileSystemError> {
+    validate_memristor_dir_structure(root_dir)?;
+    let mut files = vec!();
+    let mut dir_stack = vec!(root_dir.join("typst"));
+    while let Some(dir) = dir_stack.pop() {
+        let contents =
+            fs::read_dir(&dir).map_err(|_| FileSystemError::ReadDirError { path: dir.clone().into() })?;
+        for entry in contents {
+            let entry = entry.map_err(|_| FileSystemError::ReadDirError { path: dir.clone().into() })?;
+            let path = entry.path();
+            if path.is_dir() {
+                dir_stack.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "typ") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -292,4 +521,16 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(expected, result.unwrap());
     }
+
+    #[test]
+    fn typst_files_are_sorted() {
+        let test_fs = make_test_fs("populated");
+        let files = typst_files(&test_fs).unwrap();
+        let expected: Vec<PathBuf> = vec![
+            "./test/test_fs/populated/typst/dir1/in_dir1.typ".into(),
+            "./test/test_fs/populated/typst/test_file.typ".into(),
+            "./test/test_fs/populated/typst/top_level.typ".into(),
+        ];
+        assert_eq!(expected, files);
+    }
 }
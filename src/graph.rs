@@ -0,0 +1,396 @@
+#![allow(dead_code, unused)]
+
+// A graph of the notes in the vault and the links between them. Positions
+// come from a simple force directed layout which is run in the background
+// when the graph is rebuilt, notes keep their position between rebuilds so
+// the graph doesn't jump around every time a file is saved.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use iced::{mouse, Color, Element, Length, Padding, Point, Rectangle, Renderer, Task, Theme, Vector};
+use iced::widget::{canvas, column, pick_list, row, text_input};
+use iced::widget::canvas::{Event, Frame, Geometry, Path as CanvasPath, Stroke, Text};
+
+use crate::index::LinkIndex;
+use crate::styles;
+
+const ALL_FOLDERS: &str = "All folders";
+const NODE_RADIUS: f32 = 6.0;
+const LAYOUT_ITERATIONS: usize = 200;
+// When every note already has a position the layout only needs to settle
+const SETTLE_ITERATIONS: usize = 20;
+// The distance linked notes settle at
+const SPRING_LENGTH: f32 = 60.0;
+const MIN_ZOOM: f32 = 0.1;
+const MAX_ZOOM: f32 = 5.0;
+
+#[derive(Debug, Clone)]
+pub struct GraphNode {
+    pub path: PathBuf,
+    pub title: String,
+    // Relative to the vault's typst directory, empty for the top level
+    pub folder: String,
+    pub position: Point,
+}
+
+pub struct GraphView {
+    nodes: Vec<GraphNode>,
+    edges: Vec<(usize, usize)>,
+    search: String,
+    folder: String,
+    // Layouts finishing after a newer rebuild are thrown away
+    layout_generation: u64,
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    SearchChanged(String),
+    FolderSelected(String),
+    LayoutDone(u64, Vec<Point>),
+    // Handled at the Layout level, the same way as opening from the file tree
+    OpenFile(PathBuf),
+}
+
+impl GraphView {
+    pub fn new() -> Self {
+        GraphView {
+            nodes: vec!(),
+            edges: vec!(),
+            search: String::new(),
+            folder: ALL_FOLDERS.into(),
+            layout_generation: 0,
+        }
+    }
+
+    // Rebuilds the nodes and edges from the link index, the new positions
+    // arrive with LayoutDone
+    pub fn rebuild(&mut self, index: &LinkIndex) -> Task<Message> {
+        self.layout_generation += 1;
+        let Some(root_dir) = index.root_dir() else {
+            self.nodes.clear();
+            self.edges.clear();
+            return Task::none();
+        };
+        let typst_dir = root_dir.join("typst");
+
+        let previous: HashMap<PathBuf, Point> = self.nodes.drain(..)
+            .map(|node| (node.path, node.position))
+            .collect();
+        let mut paths: Vec<&Path> = index.notes().collect();
+        paths.sort();
+
+        let count = paths.len();
+        let placed = paths.iter().all(|path| previous.contains_key(*path));
+        self.nodes = paths.iter().enumerate().map(|(i, path)| {
+            let relative = path.strip_prefix(&typst_dir).unwrap_or(path);
+            let folder = relative.parent()
+                .map(|parent| parent.to_string_lossy().replace('\\', "/"))
+                .unwrap_or_default();
+            GraphNode {
+                path: path.to_path_buf(),
+                title: path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default(),
+                folder,
+                position: previous.get(*path).copied().unwrap_or_else(|| initial_position(i, count)),
+            }
+        })
+        .collect();
+
+        let positions: HashMap<&Path, usize> = paths.iter().enumerate().map(|(i, path)| (*path, i)).collect();
+        let mut edges = BTreeSet::new();
+        for (source, path) in paths.iter().enumerate() {
+            for link in index.links_from(path) {
+                if let Some(&target) = positions.get(link.target.as_path())
+                    && target != source
+                {
+                    edges.insert((source.min(target), source.max(target)));
+                }
+            }
+        }
+        self.edges = edges.into_iter().collect();
+
+        if self.folder != ALL_FOLDERS && !self.nodes.iter().any(|node| folder_name(&node.folder) == self.folder) {
+            self.folder = ALL_FOLDERS.into();
+        }
+
+        let mut positions: Vec<Point> = self.nodes.iter().map(|node| node.position).collect();
+        let edges = self.edges.clone();
+        let iterations = if placed { SETTLE_ITERATIONS } else { LAYOUT_ITERATIONS };
+        let generation = self.layout_generation;
+        Task::perform(
+            async move {
+                layout(&mut positions, &edges, iterations);
+                positions
+            },
+            move |positions| Message::LayoutDone(generation, positions)
+        )
+    }
+
+    pub fn update(&mut self, message: Message) {
+        match message {
+            Message::SearchChanged(search) => self.search = search,
+            Message::FolderSelected(folder) => self.folder = folder,
+            Message::LayoutDone(generation, positions) => {
+                if generation == self.layout_generation && positions.len() == self.nodes.len() {
+                    for (node, position) in self.nodes.iter_mut().zip(positions) {
+                        node.position = position;
+                    }
+                }
+            }
+            Message::OpenFile(_) => unreachable!("Handled in layout.rs"),
+        }
+    }
+
+    fn folders(&self) -> Vec<String> {
+        let folders: BTreeSet<String> = self.nodes.iter().map(|node| folder_name(&node.folder)).collect();
+        std::iter::once(ALL_FOLDERS.to_string()).chain(folders).collect()
+    }
+
+    fn is_visible(&self, node: &GraphNode) -> bool {
+        self.folder == ALL_FOLDERS || folder_name(&node.folder) == self.folder
+    }
+
+    fn is_match(&self, node: &GraphNode) -> bool {
+        !self.search.is_empty() && node.title.to_lowercase().contains(&self.search.to_lowercase())
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let controls = row![
+            text_input("Search notes", &self.search)
+                .on_input(Message::SearchChanged),
+            pick_list(self.folders(), Some(self.folder.clone()), Message::FolderSelected),
+        ]
+        .spacing(styles::SPACING_SMALL);
+
+        column![
+            controls,
+            canvas(self).width(Length::Fill).height(Length::Fill),
+        ]
+        .spacing(styles::SPACING_SMALL)
+        .padding(Padding::new(styles::SPACING_SMALL))
+        .into()
+    }
+
+    // The visible node under a point in graph coordinates
+    fn node_at(&self, point: Point) -> Option<&GraphNode> {
+        self.nodes.iter()
+            .filter(|node| self.is_visible(node))
+            .find(|node| node.position.distance(point) <= NODE_RADIUS * 1.5)
+    }
+}
+
+// The top level folder is shown as "/"
+fn folder_name(folder: &str) -> String {
+    format!("/{}", folder)
+}
+
+// New notes start spread around a circle so the layout can pull them apart
+fn initial_position(index: usize, count: usize) -> Point {
+    let angle = index as f32 / count.max(1) as f32 * std::f32::consts::TAU;
+    let radius = SPRING_LENGTH * (count as f32).sqrt();
+    Point::new(radius * angle.cos(), radius * angle.sin())
+}
+
+// Pan and zoom live in the canvas state since they only affect drawing
+#[derive(Debug)]
+pub struct Camera {
+    offset: Vector,
+    zoom: f32,
+    // Where the last drag event was, while panning
+    panning: Option<Point>,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera { offset: Vector::new(0.0, 0.0), zoom: 1.0, panning: None }
+    }
+}
+
+impl Camera {
+    fn to_graph(&self, bounds: Rectangle, point: Point) -> Point {
+        let center = Vector::new(bounds.width / 2.0, bounds.height / 2.0);
+        let screen = Point::new(point.x - center.x - self.offset.x, point.y - center.y - self.offset.y);
+        Point::new(screen.x / self.zoom, screen.y / self.zoom)
+    }
+}
+
+impl canvas::Program<Message> for GraphView {
+    type State = Camera;
+
+    fn update(
+        &self,
+        camera: &mut Camera,
+        event: &Event,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> Option<canvas::Action<Message>> {
+        let position = cursor.position_in(bounds);
+        match event {
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                let position = position?;
+                if let Some(node) = self.node_at(camera.to_graph(bounds, position)) {
+                    return Some(canvas::Action::publish(Message::OpenFile(node.path.clone())).and_capture());
+                }
+                camera.panning = Some(position);
+                Some(canvas::Action::capture())
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                camera.panning.take().map(|_| canvas::Action::capture())
+            }
+            Event::Mouse(mouse::Event::CursorMoved { .. }) => {
+                let Some(last) = camera.panning else {
+                    // Redraw so hovered nodes are highlighted
+                    return position.map(|_| canvas::Action::request_redraw());
+                };
+                let current = cursor.position_from(bounds.position())?;
+                camera.offset += (current - last);
+                camera.panning = Some(current);
+                Some(canvas::Action::request_redraw())
+            }
+            Event::Mouse(mouse::Event::WheelScrolled { delta }) => {
+                let position = position?;
+                let lines = match delta {
+                    mouse::ScrollDelta::Lines { y, .. } => *y,
+                    mouse::ScrollDelta::Pixels { y, .. } => *y / 50.0,
+                };
+                // Zoom around the cursor
+                let before = camera.to_graph(bounds, position);
+                camera.zoom = (camera.zoom * 1.1f32.powf(lines)).clamp(MIN_ZOOM, MAX_ZOOM);
+                let after = camera.to_graph(bounds, position);
+                camera.offset += (after - before) * camera.zoom;
+                Some(canvas::Action::request_redraw().and_capture())
+            }
+            _ => None,
+        }
+    }
+
+    fn draw(
+        &self,
+        camera: &Camera,
+        renderer: &Renderer,
+        theme: &Theme,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> Vec<Geometry> {
+        let palette = theme.extended_palette();
+        let hovered = cursor.position_in(bounds)
+            .and_then(|position| self.node_at(camera.to_graph(bounds, position)))
+            .map(|node| node.path.clone());
+        let searching = !self.search.is_empty();
+
+        let mut frame = Frame::new(renderer, bounds.size());
+        frame.translate(Vector::new(bounds.width / 2.0, bounds.height / 2.0) + camera.offset);
+        frame.scale(camera.zoom);
+
+        let edge_stroke = Stroke::default()
+            .with_width(1.0 / camera.zoom)
+            .with_color(palette.background.strong.color);
+        for &(a, b) in &self.edges {
+            let (a, b) = (&self.nodes[a], &self.nodes[b]);
+            if self.is_visible(a) && self.is_visible(b) {
+                frame.stroke(&CanvasPath::line(a.position, b.position), edge_stroke);
+            }
+        }
+
+        for node in self.nodes.iter().filter(|node| self.is_visible(node)) {
+            let is_hovered = hovered.as_deref() == Some(node.path.as_path());
+            let color = if self.is_match(node) || is_hovered {
+                palette.primary.strong.color
+            } else if searching {
+                // Everything else fades out while searching
+                palette.background.strong.color
+            } else {
+                palette.background.strongest.color
+            };
+            frame.fill(&CanvasPath::circle(node.position, NODE_RADIUS), color);
+
+            // Labels get cluttered when zoomed out
+            if camera.zoom >= 0.6 || is_hovered || self.is_match(node) {
+                frame.fill_text(Text {
+                    content: node.title.clone(),
+                    position: Point::new(node.position.x + NODE_RADIUS * 1.5, node.position.y - NODE_RADIUS),
+                    color: palette.background.base.text,
+                    size: 12.0.into(),
+                    ..Text::default()
+                });
+            }
+        }
+
+        vec![frame.into_geometry()]
+    }
+
+    fn mouse_interaction(&self, camera: &Camera, bounds: Rectangle, cursor: mouse::Cursor) -> mouse::Interaction {
+        if camera.panning.is_some() {
+            return mouse::Interaction::Grabbing;
+        }
+        match cursor.position_in(bounds) {
+            Some(position) if self.node_at(camera.to_graph(bounds, position)).is_some() => mouse::Interaction::Pointer,
+            Some(_) => mouse::Interaction::Grab,
+            None => mouse::Interaction::default(),
+        }
+    }
+}
+
+/////////// Logic ///////////////////
+
+// Fruchterman-Reingold style layout, every node pushes every other node away
+// and edges act as springs. The step size cools so the layout settles.
+pub fn layout(positions: &mut [Point], edges: &[(usize, usize)], iterations: usize) {
+    let count = positions.len();
+    if count < 2 {
+        return;
+    }
+    let k = SPRING_LENGTH;
+    let mut temperature = k * 2.0;
+    let cooling = temperature / iterations.max(1) as f32;
+
+    for _ in 0..iterations {
+        let mut forces = vec![Vector::new(0.0, 0.0); count];
+
+        for i in 0..count {
+            for j in (i + 1)..count {
+                let mut delta = positions[i] - positions[j];
+                let mut distance = (delta.x * delta.x + delta.y * delta.y).sqrt();
+                if distance < 0.01 {
+                    // Nudge overlapping nodes apart deterministically
+                    delta = Vector::new(0.01 * (i as f32 - j as f32), 0.01);
+                    distance = 0.01;
+                }
+                let push = delta * (k * k / (distance * distance));
+                forces[i] += push;
+                forces[j] -= push;
+            }
+        }
+
+        for &(a, b) in edges {
+            let delta = positions[a] - positions[b];
+            let distance = (delta.x * delta.x + delta.y * delta.y).sqrt().max(0.01);
+            let pull = delta * (distance / k);
+            forces[a] -= pull;
+            forces[b] += pull;
+        }
+
+        for (position, force) in positions.iter_mut().zip(forces) {
+            let length = (force.x * force.x + force.y * force.y).sqrt();
+            if length > 0.0 {
+                *position += force * (length.min(temperature) / length);
+            }
+        }
+        temperature = (temperature - cooling).max(0.5);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linked_nodes_end_up_closer() {
+        let mut positions: Vec<Point> = (0..4).map(|i| initial_position(i, 4)).collect();
+        layout(&mut positions, &[(0, 2)], LAYOUT_ITERATIONS);
+        let linked = positions[0].distance(positions[2]);
+        let unlinked = positions[1].distance(positions[3]);
+        assert!(linked < unlinked, "{} should be less than {}", linked, unlinked);
+        assert!(positions.iter().all(|position| position.x.is_finite() && position.y.is_finite()));
+    }
+}
@@ -5,10 +5,12 @@ use iced::{Element, Padding, Length, Border, Color};
 use iced::border::Radius;
 use iced::widget::{row, Column, text, mouse_area, column, container, rule, Space, button};
 use iced::widget::container::Style;
+use iced::widget::pane_grid::Axis;
 use thiserror::Error;
 
 use crate::styles;
 use crate::components;
+use crate::content::{TabMessage, ZoomMessage};
 
 #[derive(Debug, Clone)]
 pub enum Message {
@@ -16,11 +18,31 @@ pub enum Message {
     CloseMenu,
     OpenMenu,
     OpenDirectory,
+    ToggleReplace,
+    ToggleCheck,
+    ToggleGraph,
+    ToggleCalendar,
+    OpenToday,
+    ToggleDarkMode,
 
     // These are handled in ContentArea
+    ToggleOutline,
     ToggleEditor,
     TogglePreview,
+    Tab(TabMessage),
+    Zoom(ZoomMessage),
 
+    // These act on the content pane the header belongs to
+    SplitPane(Axis),
+    ClosePane,
+
+}
+
+// What the tab strip needs to know about an open document
+pub struct Tab {
+    pub title: String,
+    pub dirty: bool,
+    pub active: bool,
 }
 
 
@@ -40,7 +62,19 @@ impl MenuHeader {
                     button("Collapse")
                         .on_press(Message::CloseMenu),
                     button("Open")
-                        .on_press(Message::OpenDirectory) 
+                        .on_press(Message::OpenDirectory),
+                    button("Replace")
+                        .on_press(Message::ToggleReplace),
+                    button("Check")
+                        .on_press(Message::ToggleCheck),
+                    button("Graph")
+                        .on_press(Message::ToggleGraph),
+                    button("Today")
+                        .on_press(Message::OpenToday),
+                    button("Calendar")
+                        .on_press(Message::ToggleCalendar),
+                    button("Dark")
+                        .on_press(Message::ToggleDarkMode),
                 ]
                 .spacing(10)
             )
@@ -80,12 +114,17 @@ impl ContentHeader {
             Message::OpenMenu => { self.menu_open = true },
             Message::ToggleEditor => { self.editor_open != !self.editor_open; },
             Message::TogglePreview => { self.preview_open != self.preview_open; },
+            Message::ToggleOutline => { unreachable!("Handled in layout.rs")  }
             Message::OpenDirectory => { unreachable!("Handled in layout.rs")  }
+            Message::ToggleReplace | Message::ToggleCheck | Message::ToggleGraph => { unreachable!("Handled in layout.rs")  }
+            Message::ToggleCalendar | Message::OpenToday | Message::ToggleDarkMode => { unreachable!("Handled in layout.rs")  }
+            Message::Tab(_) | Message::Zoom(_) => { unreachable!("Handled in content.rs")  }
+            Message::SplitPane(_) | Message::ClosePane => { unreachable!("Handled in layout.rs")  }
         }
     }
 
 
-    pub fn view(&self) -> Element<'_, Message> {
+    pub fn view(&self, tabs: Vec<Tab>, focused: bool, can_close: bool) -> Element<'_, Message> {
         let mut left_buttons = row! [
                 Space::new().width(10),
             ]
@@ -95,6 +134,11 @@ impl ContentHeader {
             if !self.menu_open { left_buttons.push(button("Expand").on_press(Message::OpenMenu)) }
             else { left_buttons };
 
+        left_buttons = left_buttons.push(
+            button("Outline")
+                .on_press(Message::ToggleOutline)
+        );
+
         left_buttons = left_buttons.push(
             button("Editor")
                 .on_press(Message::ToggleEditor)
@@ -105,16 +149,80 @@ impl ContentHeader {
                 .on_press(Message::TogglePreview)
         );
 
+        left_buttons = left_buttons
+            .push(button("−").on_press(Message::Zoom(ZoomMessage::Out)))
+            .push(button("+").on_press(Message::Zoom(ZoomMessage::In)))
+            .push(button("Fit width").on_press(Message::Zoom(ZoomMessage::FitWidth)))
+            .push(button("Fit page").on_press(Message::Zoom(ZoomMessage::FitPage)));
+
+        left_buttons = left_buttons
+            .push(button("Split right").on_press(Message::SplitPane(Axis::Vertical)))
+            .push(button("Split down").on_press(Message::SplitPane(Axis::Horizontal)))
+            .push(button("Close pane").on_press_maybe(can_close.then_some(Message::ClosePane)));
+
         let header_contents = container(
                 left_buttons
             )
             .center_y(styles::HEADER_HEIGHT);
 
-        column! [
+        // Highlight the header of the pane files will open in
+        let header_contents = header_contents.style(move |theme: &iced::Theme| {
+            let palette = theme.extended_palette();
+            Style {
+                background: focused.then_some(palette.background.weak.color.into()),
+                ..Style::default()
+            }
+        });
+
+        let mut column = column! [
             header_contents,
             components::hrule(),
-        ]
+        ];
+        if !tabs.is_empty() {
+            column = column.push(tab_strip(tabs)).push(components::hrule());
+        }
+        column
         .width(Length::Fill)
         .into()
     }
 }
+
+// Click to select, drag onto another tab to reorder, middle click to close
+fn tab_strip(tabs: Vec<Tab>) -> Element<'static, Message> {
+    let mut strip = row![].spacing(2);
+    for (index, tab) in tabs.into_iter().enumerate() {
+        let title = if tab.dirty { format!("● {}", tab.title) } else { tab.title };
+        let label = row![
+            text(title),
+            button(text("×"))
+                .padding(Padding::ZERO.left(4).right(4))
+                .style(button::text)
+                .on_press(Message::Tab(TabMessage::Close(index))),
+        ]
+        .spacing(styles::SPACING_SMALL)
+        .align_y(iced::Alignment::Center);
+
+        let active = tab.active;
+        let tab_contents = container(label)
+            .padding(Padding::new(4.0).left(styles::SPACING_SMALL))
+            .style(move |theme: &iced::Theme| {
+                let palette = theme.extended_palette();
+                let background = if active { palette.background.base.color } else { palette.background.weak.color };
+                Style {
+                    background: Some(background.into()),
+                    border: Border { radius: Radius::new(0), width: 1.0, color: palette.background.strong.color },
+                    ..Style::default()
+                }
+            });
+
+        strip = strip.push(
+            mouse_area(tab_contents)
+                .on_press(Message::Tab(TabMessage::Drag(index)))
+                .on_release(Message::Tab(TabMessage::Drop(index)))
+                .on_middle_press(Message::Tab(TabMessage::Close(index)))
+        );
+    }
+    container(strip)
+        .padding(Padding::ZERO.left(10).top(4))
+        .into()
+}
@@ -0,0 +1,563 @@
+#![allow(dead_code, unused)]
+
+// A small Typst highlighter for the editor. This is not a full parser - it
+// tracks just enough state (markup, code, math, comments and raw blocks)
+// between lines to colour things correctly as the user types.
+
+use std::ops::Range;
+
+use iced::{Font, Theme};
+use iced::font::{Weight, Style};
+use iced::advanced::text::highlighter::{self, Format};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Highlight {
+    Heading,
+    Strong,
+    Emphasis,
+    Comment,
+    String,
+    Raw,
+    Keyword,
+    Function,
+    Number,
+    Math,
+    Label,
+    Reference,
+    Escape,
+    Marker,
+}
+
+const KEYWORDS: [&str; 22] = [
+    "let", "set", "show", "import", "include", "if", "else", "for", "in", "while",
+    "break", "continue", "return", "context", "none", "auto", "true", "false",
+    "not", "and", "or", "as",
+];
+
+// Keywords which start a statement, the code mode they open lasts until the
+// end of the line
+const STATEMENTS: [&str; 10] = [
+    "let", "set", "show", "import", "include", "if", "for", "while", "return", "context",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Markup,
+    Code,
+    Math,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Closer {
+    Root,
+    Char(char),
+    EndOfLine,
+}
+
+// The lexer state at the start of a line
+#[derive(Debug, Clone, PartialEq)]
+pub struct State {
+    stack: Vec<(Mode, Closer)>,
+    comment_depth: usize,
+    in_raw_block: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            stack: vec![(Mode::Markup, Closer::Root)],
+            comment_depth: 0,
+            in_raw_block: false,
+        }
+    }
+}
+
+pub struct TypstHighlighter {
+    current_line: usize,
+    // line_states[n] is the state at the start of line n
+    line_states: Vec<State>,
+}
+
+impl highlighter::Highlighter for TypstHighlighter {
+    type Settings = ();
+    type Highlight = Highlight;
+    type Iterator<'a> = std::vec::IntoIter<(Range<usize>, Highlight)>;
+
+    fn new(_settings: &Self::Settings) -> Self {
+        TypstHighlighter {
+            current_line: 0,
+            line_states: vec![State::default()],
+        }
+    }
+
+    fn update(&mut self, _new_settings: &Self::Settings) {}
+
+    fn change_line(&mut self, line: usize) {
+        self.current_line = line.min(self.line_states.len() - 1);
+        self.line_states.truncate(self.current_line + 1);
+    }
+
+    fn highlight_line(&mut self, line: &str) -> Self::Iterator<'_> {
+        let mut state = self.line_states
+            .get(self.current_line)
+            .cloned()
+            .unwrap_or_default();
+        let spans = highlight_line(&mut state, line);
+
+        self.line_states.truncate(self.current_line + 1);
+        self.line_states.push(state);
+        self.current_line += 1;
+
+        spans.into_iter()
+    }
+
+    fn current_line(&self) -> usize {
+        self.current_line
+    }
+}
+
+// Colours come from the theme palette so the editor follows the app theme
+pub fn to_format(highlight: &Highlight, theme: &Theme) -> Format<Font> {
+    let palette = theme.extended_palette();
+    let bold = Font { weight: Weight::Bold, ..Font::DEFAULT };
+    let italic = Font { style: Style::Italic, ..Font::DEFAULT };
+
+    let (color, font) = match highlight {
+        Highlight::Heading => (palette.primary.strong.color, Some(bold)),
+        Highlight::Strong => (palette.background.base.text, Some(bold)),
+        Highlight::Emphasis => (palette.background.base.text, Some(italic)),
+        Highlight::Comment => (palette.background.strongest.color, Some(italic)),
+        Highlight::String => (palette.success.base.color, None),
+        Highlight::Raw => (palette.success.strong.color, None),
+        Highlight::Keyword => (palette.danger.base.color, None),
+        Highlight::Function => (palette.primary.base.color, None),
+        Highlight::Number => (palette.warning.strong.color, None),
+        Highlight::Math => (palette.warning.base.color, None),
+        Highlight::Label => (palette.secondary.strong.color, None),
+        Highlight::Reference => (palette.primary.weak.color, None),
+        Highlight::Escape => (palette.danger.weak.color, None),
+        Highlight::Marker => (palette.primary.base.color, Some(bold)),
+    };
+
+    Format { color: Some(color), font }
+}
+
+
+/////////// Logic ///////////////////
+
+struct Lexer<'a> {
+    line: &'a str,
+    chars: Vec<(usize, char)>,
+    pos: usize,
+    spans: Vec<(Range<usize>, Highlight)>,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self, ahead: usize) -> Option<char> {
+        self.chars.get(self.pos + ahead).map(|(_, c)| *c)
+    }
+
+    fn offset(&self, index: usize) -> usize {
+        self.chars.get(index).map(|(offset, _)| *offset).unwrap_or(self.line.len())
+    }
+
+    fn starts_with(&self, pattern: &str) -> bool {
+        self.line[self.offset(self.pos)..].starts_with(pattern)
+    }
+
+    // Highlights from the current position up to (not including) char index `end`
+    fn mark_until(&mut self, end: usize, highlight: Highlight) {
+        let range = self.offset(self.pos)..self.offset(end);
+        if !range.is_empty() {
+            match self.spans.last_mut() {
+                Some((last, last_highlight)) if last.end == range.start && *last_highlight == highlight => {
+                    last.end = range.end;
+                }
+                _ => self.spans.push((range, highlight)),
+            }
+        }
+        self.pos = end;
+    }
+
+    fn mark(&mut self, count: usize, highlight: Highlight) {
+        self.mark_until((self.pos + count).min(self.chars.len()), highlight);
+    }
+
+    // Index of the first char after the identifier starting at `start`
+    fn ident_end(&self, start: usize) -> usize {
+        let mut end = start;
+        while let Some((_, c)) = self.chars.get(end) {
+            if c.is_alphanumeric() || *c == '_' || (*c == '-' && end > start) {
+                end += 1;
+            } else {
+                break;
+            }
+        }
+        end
+    }
+
+    fn find(&self, pattern: &str, from: usize) -> Option<usize> {
+        let byte_start = self.offset(from);
+        let found = self.line[byte_start..].find(pattern)? + byte_start;
+        self.chars.iter().position(|(offset, _)| *offset == found)
+    }
+
+    fn ident(&self, start: usize, end: usize) -> &'a str {
+        &self.line[self.offset(start)..self.offset(end)]
+    }
+}
+
+// Highlights a single line, updating the state to be the one at the start
+// of the next line
+pub fn highlight_line(state: &mut State, line: &str) -> Vec<(Range<usize>, Highlight)> {
+    let mut lexer = Lexer {
+        line,
+        chars: line.char_indices().collect(),
+        pos: 0,
+        spans: vec!(),
+    };
+    let mut line_start = true;
+
+    while lexer.pos < lexer.chars.len() {
+        if state.comment_depth > 0 {
+            block_comment(&mut lexer, state);
+            continue;
+        }
+        if state.in_raw_block {
+            match lexer.find("```", lexer.pos) {
+                Some(end) => {
+                    lexer.mark_until(end + 3, Highlight::Raw);
+                    state.in_raw_block = false;
+                }
+                None => lexer.mark_until(lexer.chars.len(), Highlight::Raw),
+            }
+            continue;
+        }
+
+        // Comments look the same in every mode
+        if lexer.starts_with("//") {
+            lexer.mark_until(lexer.chars.len(), Highlight::Comment);
+            break;
+        }
+        if lexer.starts_with("/*") {
+            state.comment_depth = 1;
+            lexer.mark(2, Highlight::Comment);
+            continue;
+        }
+
+        let c = lexer.peek(0).unwrap();
+        let (mode, closer) = *state.stack.last().unwrap();
+        if closer == Closer::Char(c) {
+            state.stack.pop();
+            if mode == Mode::Math {
+                lexer.mark(1, Highlight::Math);
+            } else {
+                lexer.pos += 1;
+            }
+            continue;
+        }
+
+        match mode {
+            Mode::Markup => markup(&mut lexer, state, line_start),
+            Mode::Code => code(&mut lexer, state),
+            Mode::Math => math(&mut lexer, state),
+        }
+        if !c.is_whitespace() {
+            line_start = false;
+        }
+    }
+
+    // Statements end with the line
+    while let Some((_, Closer::EndOfLine)) = state.stack.last() {
+        state.stack.pop();
+    }
+    lexer.spans
+}
+
+fn block_comment(lexer: &mut Lexer, state: &mut State) {
+    let mut end = lexer.pos;
+    while end < lexer.chars.len() && state.comment_depth > 0 {
+        let rest = &lexer.line[lexer.offset(end)..];
+        if rest.starts_with("/*") {
+            state.comment_depth += 1;
+            end += 2;
+        } else if rest.starts_with("*/") {
+            state.comment_depth -= 1;
+            end += 2;
+        } else {
+            end += 1;
+        }
+    }
+    lexer.mark_until(end.min(lexer.chars.len()), Highlight::Comment);
+}
+
+fn markup(lexer: &mut Lexer, state: &mut State, line_start: bool) {
+    let c = lexer.peek(0).unwrap();
+    let next = lexer.peek(1);
+
+    match c {
+        '=' if line_start => {
+            let mut level = 0;
+            while lexer.peek(level) == Some('=') {
+                level += 1;
+            }
+            if lexer.peek(level).is_none_or(|c| c.is_whitespace()) {
+                lexer.mark_until(lexer.chars.len(), Highlight::Heading);
+            } else {
+                lexer.pos += level;
+            }
+        }
+        '-' | '+' | '/' if line_start && next.is_some_and(|c| c.is_whitespace()) => {
+            lexer.mark(1, Highlight::Marker);
+        }
+        '\\' => lexer.mark(2, Highlight::Escape),
+        '`' => raw(lexer, state),
+        '$' => {
+            state.stack.push((Mode::Math, Closer::Char('$')));
+            lexer.mark(1, Highlight::Math);
+        }
+        '[' => {
+            state.stack.push((Mode::Markup, Closer::Char(']')));
+            lexer.pos += 1;
+        }
+        '<' => {
+            let end = lexer.ident_end(lexer.pos + 1);
+            if end > lexer.pos + 1 && lexer.chars.get(end).is_some_and(|(_, c)| *c == '>') {
+                lexer.mark_until(end + 1, Highlight::Label);
+            } else {
+                lexer.pos += 1;
+            }
+        }
+        '@' => {
+            let end = lexer.ident_end(lexer.pos + 1);
+            if end > lexer.pos + 1 {
+                lexer.mark_until(end, Highlight::Reference);
+            } else {
+                lexer.pos += 1;
+            }
+        }
+        '*' | '_' => {
+            let follows_word = lexer.pos > 0
+                && lexer.chars[lexer.pos - 1].1.is_alphanumeric();
+            let highlight = if c == '*' { Highlight::Strong } else { Highlight::Emphasis };
+            let closing = lexer.find(&c.to_string(), lexer.pos + 1);
+            match closing {
+                Some(end) if !follows_word => lexer.mark_until(end + 1, highlight),
+                _ => lexer.pos += 1,
+            }
+        }
+        '#' => hash(lexer, state),
+        _ => lexer.pos += 1,
+    }
+}
+
+fn raw(lexer: &mut Lexer, state: &mut State) {
+    if lexer.starts_with("```") {
+        match lexer.find("```", lexer.pos + 3) {
+            Some(end) => lexer.mark_until(end + 3, Highlight::Raw),
+            None => {
+                lexer.mark_until(lexer.chars.len(), Highlight::Raw);
+                state.in_raw_block = true;
+            }
+        }
+    } else {
+        match lexer.find("`", lexer.pos + 1) {
+            Some(end) => lexer.mark_until(end + 1, Highlight::Raw),
+            None => lexer.mark_until(lexer.chars.len(), Highlight::Raw),
+        }
+    }
+}
+
+// An embedded code expression in markup or math
+fn hash(lexer: &mut Lexer, state: &mut State) {
+    match lexer.peek(1) {
+        Some('{') => {
+            state.stack.push((Mode::Code, Closer::Char('}')));
+            lexer.mark(2, Highlight::Keyword);
+        }
+        Some('(') => {
+            state.stack.push((Mode::Code, Closer::Char(')')));
+            lexer.mark(2, Highlight::Function);
+        }
+        Some('[') => {
+            state.stack.push((Mode::Markup, Closer::Char(']')));
+            lexer.mark(2, Highlight::Function);
+        }
+        Some(c) if c.is_alphabetic() || c == '_' => {
+            let mut end = lexer.ident_end(lexer.pos + 1);
+            let ident = lexer.ident(lexer.pos + 1, end);
+
+            if KEYWORDS.contains(&ident) {
+                if STATEMENTS.contains(&ident) {
+                    state.stack.push((Mode::Code, Closer::EndOfLine));
+                }
+                lexer.mark_until(end, Highlight::Keyword);
+                return;
+            }
+
+            // Field access and method calls are part of the same expression
+            while lexer.chars.get(end).is_some_and(|(_, c)| *c == '.')
+                && lexer.chars.get(end + 1).is_some_and(|(_, c)| c.is_alphabetic())
+            {
+                end = lexer.ident_end(end + 1);
+            }
+            lexer.mark_until(end, Highlight::Function);
+            if lexer.peek(0) == Some('(') {
+                state.stack.push((Mode::Code, Closer::Char(')')));
+                lexer.pos += 1;
+            }
+        }
+        _ => lexer.pos += 1,
+    }
+}
+
+fn code(lexer: &mut Lexer, state: &mut State) {
+    let c = lexer.peek(0).unwrap();
+    match c {
+        '"' => string(lexer),
+        '(' => {
+            state.stack.push((Mode::Code, Closer::Char(')')));
+            lexer.pos += 1;
+        }
+        '{' => {
+            state.stack.push((Mode::Code, Closer::Char('}')));
+            lexer.pos += 1;
+        }
+        '[' => {
+            state.stack.push((Mode::Markup, Closer::Char(']')));
+            lexer.pos += 1;
+        }
+        '$' => {
+            state.stack.push((Mode::Math, Closer::Char('$')));
+            lexer.mark(1, Highlight::Math);
+        }
+        ';' => {
+            if state.stack.last().is_some_and(|(_, closer)| *closer == Closer::EndOfLine) {
+                state.stack.pop();
+            }
+            lexer.pos += 1;
+        }
+        '<' => {
+            let end = lexer.ident_end(lexer.pos + 1);
+            if end > lexer.pos + 1 && lexer.chars.get(end).is_some_and(|(_, c)| *c == '>') {
+                lexer.mark_until(end + 1, Highlight::Label);
+            } else {
+                lexer.pos += 1;
+            }
+        }
+        c if c.is_ascii_digit() => {
+            let mut end = lexer.pos;
+            while lexer.chars.get(end).is_some_and(|(_, c)| c.is_alphanumeric() || *c == '.' || *c == '%') {
+                end += 1;
+            }
+            lexer.mark_until(end, Highlight::Number);
+        }
+        c if c.is_alphabetic() || c == '_' => {
+            let end = lexer.ident_end(lexer.pos);
+            let ident = lexer.ident(lexer.pos, end);
+            if KEYWORDS.contains(&ident) {
+                lexer.mark_until(end, Highlight::Keyword);
+            } else if lexer.chars.get(end).is_some_and(|(_, c)| *c == '(' || *c == '[') {
+                lexer.mark_until(end, Highlight::Function);
+            } else {
+                lexer.pos = end;
+            }
+        }
+        _ => lexer.pos += 1,
+    }
+}
+
+fn math(lexer: &mut Lexer, state: &mut State) {
+    match lexer.peek(0).unwrap() {
+        '#' => hash(lexer, state),
+        '"' => string(lexer),
+        '\\' => lexer.mark(2, Highlight::Escape),
+        _ => lexer.mark(1, Highlight::Math),
+    }
+}
+
+fn string(lexer: &mut Lexer) {
+    let mut end = lexer.pos + 1;
+    while let Some((_, c)) = lexer.chars.get(end) {
+        end += 1;
+        match c {
+            '\\' => end += 1,
+            '"' => break,
+            _ => {}
+        }
+    }
+    lexer.mark_until(end.min(lexer.chars.len()), Highlight::String);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn highlighted<'a>(line: &'a str, spans: &[(Range<usize>, Highlight)]) -> Vec<(&'a str, Highlight)> {
+        spans.iter().map(|(range, highlight)| (&line[range.clone()], *highlight)).collect()
+    }
+
+    #[test]
+    fn markup_line() {
+        let mut state = State::default();
+        let line = "Some *bold* text with $x^2$ and <intro> see @intro";
+        let spans = highlight_line(&mut state, line);
+        assert_eq!(highlighted(line, &spans), vec![
+            ("*bold*", Highlight::Strong),
+            ("$x^2$", Highlight::Math),
+            ("<intro>", Highlight::Label),
+            ("@intro", Highlight::Reference),
+        ]);
+        assert_eq!(state, State::default());
+    }
+
+    #[test]
+    fn heading_and_comment() {
+        let mut state = State::default();
+        let line = "== Heading";
+        assert_eq!(highlighted(line, &highlight_line(&mut state, line)), vec![("== Heading", Highlight::Heading)]);
+
+        let line = "text // a comment";
+        assert_eq!(highlighted(line, &highlight_line(&mut state, line)), vec![("// a comment", Highlight::Comment)]);
+    }
+
+    #[test]
+    fn code_expressions() {
+        let mut state = State::default();
+        let line = r#"#let name = "memristor" // ok"#;
+        let spans = highlight_line(&mut state, line);
+        assert_eq!(highlighted(line, &spans), vec![
+            ("#let", Highlight::Keyword),
+            ("\"memristor\"", Highlight::String),
+            ("// ok", Highlight::Comment),
+        ]);
+        assert_eq!(state, State::default());
+
+        let line = "#text(size: 12pt)[hello]";
+        let spans = highlight_line(&mut state, line);
+        assert_eq!(highlighted(line, &spans), vec![
+            ("#text", Highlight::Function),
+            ("12pt", Highlight::Number),
+        ]);
+    }
+
+    #[test]
+    fn state_carries_across_lines() {
+        let mut state = State::default();
+        highlight_line(&mut state, "/* start of a comment");
+        assert_eq!(state.comment_depth, 1);
+        let line = "still comment */ *bold*";
+        let spans = highlight_line(&mut state, line);
+        assert_eq!(highlighted(line, &spans), vec![
+            ("still comment */", Highlight::Comment),
+            ("*bold*", Highlight::Strong),
+        ]);
+
+        highlight_line(&mut state, "#figure(");
+        let line = "  caption: [A *caption*],";
+        let spans = highlight_line(&mut state, line);
+        assert_eq!(highlighted(line, &spans), vec![("*caption*", Highlight::Strong)]);
+        highlight_line(&mut state, ")");
+        assert_eq!(state, State::default());
+    }
+}
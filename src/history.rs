@@ -0,0 +1,202 @@
+#![allow(dead_code, unused)]
+
+// Undo/redo for the editor. Each step is a snapshot of the whole document
+// taken before the edit that started it, typing is coalesced so one undo
+// removes roughly a word rather than a single character.
+
+use iced::widget::text_editor::{self, Action, Content, Cursor, Edit};
+
+const MAX_STEPS: usize = 200;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Snapshot {
+    text: String,
+    cursor: Cursor,
+}
+
+impl Snapshot {
+    fn take(content: &Content) -> Self {
+        Snapshot {
+            text: content.text(),
+            cursor: content.cursor(),
+        }
+    }
+
+    fn restore(self, content: &mut Content) {
+        *content = Content::with_text(&self.text);
+        let position = crate::content::clamp_position(content, self.cursor.position);
+        content.move_to(Cursor { position, selection: None });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EditKind {
+    Word,
+    Separator,
+    Delete,
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    // The kind of the last edit, None when the next edit always starts a new step
+    last_edit: Option<EditKind>,
+    // The document text when it was last switched away from
+    left_with: Option<String>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Call before performing an action on the content
+    pub fn record(&mut self, content: &Content, action: &Action) {
+        let Action::Edit(edit) = action else {
+            // Moving the cursor breaks up typing
+            self.last_edit = None;
+            return;
+        };
+
+        let kind = match edit {
+            _ if content.cursor().selection.is_some() => None,
+            Edit::Insert(c) if c.is_alphanumeric() || *c == '_' => Some(EditKind::Word),
+            Edit::Insert(_) => Some(EditKind::Separator),
+            Edit::Backspace | Edit::Delete => Some(EditKind::Delete),
+            Edit::Paste(_) | Edit::Enter | Edit::Indent | Edit::Unindent => None,
+        };
+
+        // Separators after a word belong to that word, a new word starts a new step
+        let coalesce = matches!(
+            (self.last_edit, kind),
+            (Some(EditKind::Word), Some(EditKind::Word))
+                | (Some(EditKind::Word), Some(EditKind::Separator))
+                | (Some(EditKind::Separator), Some(EditKind::Separator))
+                | (Some(EditKind::Delete), Some(EditKind::Delete))
+        );
+        if !coalesce {
+            self.push(Snapshot::take(content));
+        }
+        self.last_edit = kind;
+    }
+
+    // Records the whole content as a single step, used before the document
+    // is replaced from outside of the editor
+    pub fn checkpoint(&mut self, content: &Content) {
+        self.push(Snapshot::take(content));
+        self.last_edit = None;
+    }
+
+    fn push(&mut self, snapshot: Snapshot) {
+        if self.undo.last().is_some_and(|last| last.text == snapshot.text) {
+            return;
+        }
+        self.undo.push(snapshot);
+        if self.undo.len() > MAX_STEPS {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    pub fn undo(&mut self, content: &mut Content) -> bool {
+        let Some(snapshot) = self.undo.pop() else {
+            return false;
+        };
+        self.redo.push(Snapshot::take(content));
+        snapshot.restore(content);
+        self.last_edit = None;
+        true
+    }
+
+    pub fn redo(&mut self, content: &mut Content) -> bool {
+        let Some(snapshot) = self.redo.pop() else {
+            return false;
+        };
+        self.undo.push(Snapshot::take(content));
+        snapshot.restore(content);
+        self.last_edit = None;
+        true
+    }
+
+    // Remember the text when switching to another file, if the file on disk
+    // doesn't match when we come back the unsaved text is kept as an undo step
+    pub fn leave(&mut self, content: &Content) {
+        self.left_with = Some(content.text());
+        self.last_edit = None;
+    }
+
+    pub fn reenter(&mut self, content: &Content) {
+        if let Some(text) = self.left_with.take()
+            && text != content.text()
+        {
+            self.push(Snapshot { text, cursor: content.cursor() });
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_text(history: &mut History, content: &mut Content, text: &str) {
+        for c in text.chars() {
+            let action = if c == '\n' { Action::Edit(Edit::Enter) } else { Action::Edit(Edit::Insert(c)) };
+            history.record(content, &action);
+            content.perform(action);
+        }
+    }
+
+    #[test]
+    fn typing_is_undone_a_word_at_a_time() {
+        let mut history = History::new();
+        let mut content = Content::new();
+        type_text(&mut history, &mut content, "hello world");
+        assert_eq!(content.text(), "hello world");
+
+        assert!(history.undo(&mut content));
+        assert_eq!(content.text(), "hello ");
+        assert!(history.undo(&mut content));
+        assert_eq!(content.text(), "");
+        assert!(!history.undo(&mut content));
+
+        assert!(history.redo(&mut content));
+        assert!(history.redo(&mut content));
+        assert_eq!(content.text(), "hello world");
+    }
+
+    #[test]
+    fn paste_is_a_single_step() {
+        let mut history = History::new();
+        let mut content = Content::new();
+        type_text(&mut history, &mut content, "a");
+        let paste = Action::Edit(Edit::Paste(String::from("pasted text").into()));
+        history.record(&content, &paste);
+        content.perform(paste);
+        type_text(&mut history, &mut content, "b");
+
+        history.undo(&mut content);
+        assert_eq!(content.text(), "apasted text");
+        history.undo(&mut content);
+        assert_eq!(content.text(), "a");
+    }
+
+    #[test]
+    fn new_edits_clear_redo() {
+        let mut history = History::new();
+        let mut content = Content::new();
+        type_text(&mut history, &mut content, "one two");
+        history.undo(&mut content);
+        type_text(&mut history, &mut content, "three");
+        assert!(!history.can_redo());
+        assert_eq!(content.text(), "one three");
+    }
+}
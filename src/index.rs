@@ -0,0 +1,198 @@
+#![allow(dead_code, unused)]
+
+// An index of the links between every note in the vault, used to find which
+// notes link to the one that's open, along with the tags each note declares.
+// Notes are only re-read when their modification time changes so refreshing
+// is cheap.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::filetree;
+use crate::links;
+use crate::tags;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedLink {
+    pub target: PathBuf,
+    pub line: usize,
+    // The line of the source the link is on
+    pub context: String,
+}
+
+#[derive(Debug, Clone)]
+struct IndexedNote {
+    modified: Option<SystemTime>,
+    links: Vec<IndexedLink>,
+    tags: Vec<String>,
+}
+
+// A note linking to another, and where
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backlink {
+    pub source: PathBuf,
+    pub line: usize,
+    pub context: String,
+}
+
+#[derive(Debug, Default)]
+pub struct LinkIndex {
+    root_dir: Option<PathBuf>,
+    notes: HashMap<PathBuf, IndexedNote>,
+}
+
+impl LinkIndex {
+    pub fn new(root_dir: Option<&Path>) -> Self {
+        let mut index = LinkIndex {
+            root_dir: root_dir.map(Path::to_path_buf),
+            notes: HashMap::new(),
+        };
+        index.refresh();
+        index
+    }
+
+    // Picks up notes which were added, removed or changed on disk, returning
+    // their paths
+    pub fn refresh(&mut self) -> Vec<PathBuf> {
+        let Some(root_dir) = self.root_dir.clone() else {
+            return vec!();
+        };
+        let Ok(files) = filetree::typst_files(&root_dir) else {
+            return vec!();
+        };
+        let files: HashSet<PathBuf> = files.into_iter().collect();
+
+        let mut changed: Vec<PathBuf> = self.notes.keys()
+            .filter(|path| !files.contains(*path))
+            .cloned()
+            .collect();
+        self.notes.retain(|path, _| files.contains(path));
+        for file in files {
+            let modified = modified(&file);
+            let unchanged = self.notes.get(&file)
+                .is_some_and(|note| note.modified.is_some() && note.modified == modified);
+            if !unchanged {
+                self.update_file(&file);
+                changed.push(file);
+            }
+        }
+        changed
+    }
+
+    // Re-reads a single note, call after it's written
+    pub fn update_file(&mut self, path: &Path) {
+        let Some(root_dir) = &self.root_dir else {
+            return;
+        };
+        let Ok(source) = fs::read_to_string(path) else {
+            self.notes.remove(path);
+            return;
+        };
+
+        let lines: Vec<&str> = source.lines().collect();
+        let links = links::find_links(&source).into_iter()
+            .filter_map(|reference| Some(IndexedLink {
+                target: links::resolve(root_dir, &reference.target)?,
+                line: reference.line,
+                context: lines[reference.line].trim().to_string(),
+            }))
+            .collect();
+
+        self.notes.insert(path.to_path_buf(), IndexedNote {
+            modified: modified(path),
+            links,
+            tags: tags::parse_tags(&source),
+        });
+    }
+
+    pub fn root_dir(&self) -> Option<&Path> {
+        self.root_dir.as_deref()
+    }
+
+    // Every indexed note, in no particular order
+    pub fn notes(&self) -> impl Iterator<Item = &Path> {
+        self.notes.keys().map(PathBuf::as_path)
+    }
+
+    pub fn tags(&self, note: &Path) -> &[String] {
+        self.notes.get(note).map(|note| note.tags.as_slice()).unwrap_or_default()
+    }
+
+    pub fn tagged_notes(&self) -> impl Iterator<Item = (&PathBuf, &[String])> {
+        self.notes.iter().map(|(path, note)| (path, note.tags.as_slice()))
+    }
+
+    // Every note under a tag, including those with nested tags
+    pub fn notes_tagged(&self, tag: &str) -> Vec<PathBuf> {
+        let mut notes: Vec<PathBuf> = self.notes.iter()
+            .filter(|(_, note)| tags::has_tag(&note.tags, tag))
+            .map(|(path, _)| path.clone())
+            .collect();
+        notes.sort();
+        notes
+    }
+
+    pub fn links_from(&self, note: &Path) -> &[IndexedLink] {
+        self.notes.get(note).map(|note| note.links.as_slice()).unwrap_or_default()
+    }
+
+    // Every link to the note from other notes, sorted by source
+    pub fn backlinks(&self, note: &Path) -> Vec<Backlink> {
+        let mut backlinks: Vec<Backlink> = self.notes.iter()
+            .filter(|(source, _)| source.as_path() != note)
+            .flat_map(|(source, indexed)| {
+                indexed.links.iter()
+                    .filter(|link| link.target == note)
+                    .map(|link| Backlink {
+                        source: source.clone(),
+                        line: link.line,
+                        context: link.context.clone(),
+                    })
+            })
+            .collect();
+        backlinks.sort_by(|a, b| (&a.source, a.line).cmp(&(&b.source, b.line)));
+        backlinks
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn backlinks_follow_changes() {
+        let vault = TempDir::new("memristor-index").unwrap();
+        let typst = vault.path().join("typst");
+        fs::create_dir_all(typst.join("dir")).unwrap();
+        fs::create_dir_all(vault.path().join("pdf")).unwrap();
+        fs::write(typst.join("a.typ"), "= A\nSee [[dir/b]] for more").unwrap();
+        fs::write(typst.join("dir/b.typ"), "= B").unwrap();
+
+        let mut index = LinkIndex::new(Some(vault.path()));
+        let b = typst.join("dir/b.typ");
+        assert_eq!(index.backlinks(&b), vec![Backlink {
+            source: typst.join("a.typ"),
+            line: 1,
+            context: "See [[dir/b]] for more".into(),
+        }]);
+
+        fs::write(typst.join("c.typ"), "#note(\"dir/b\")").unwrap();
+        fs::write(typst.join("a.typ"), "= A").unwrap();
+        index.update_file(&typst.join("a.typ"));
+        index.refresh();
+        let sources: Vec<PathBuf> = index.backlinks(&b).into_iter().map(|link| link.source).collect();
+        assert_eq!(sources, vec![typst.join("c.typ")]);
+
+        fs::remove_file(typst.join("c.typ")).unwrap();
+        assert_eq!(index.refresh(), vec![typst.join("c.typ")]);
+        assert!(index.backlinks(&b).is_empty());
+        assert!(index.refresh().is_empty());
+    }
+}
@@ -1,16 +1,33 @@
 #![allow(dead_code, unused)]
 
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::env::home_dir;
+use std::thread;
+use std::time::Duration;
 
-use iced::widget::{responsive, container, column};
-use iced::widget::pane_grid::{self, PaneGrid, Axis};
-use iced::{Element, Fill, Task};
-use rfd::FileDialog;
+use iced::widget::{button, responsive, container, column, row, text, text_editor};
+use iced::widget::pane_grid::{self, PaneGrid, Axis, Edge, Node};
+use iced::futures::channel::mpsc;
+use iced::{event, keyboard, window, Alignment, Element, Event, Fill, Subscription, Task, Theme};
+use rfd::{FileDialog, MessageButtons, MessageDialog, MessageDialogResult};
 
 use crate::filetree::{self, FileTree};
 use crate::content::{self, ContentArea};
 use crate::header::{self, MenuHeader, ContentHeader};
+use crate::replace::{self, ReplacePanel};
+use crate::links;
+use crate::index::{Backlink, LinkIndex};
+use crate::graph::{self, GraphView};
+use crate::rename::RenamePlan;
+use crate::check::{self, CheckPanel};
+use crate::tags::{self, TagBrowser};
+use crate::metadata::{self, MetadataStore};
+use crate::daily::{self, Calendar, Date};
+use crate::templates;
+use crate::components;
+use crate::styles;
+use crate::session::{self, Session};
 use crate::typst::TypstContext;
 
 use crate::settings::Settings;
@@ -20,37 +37,86 @@ pub enum Message {
     PaneClicked(pane_grid::Pane),
     PaneResized(pane_grid::ResizeEvent),
     FiletreeMessage(filetree::Message),
-    ContentAreaMessage(content::Message),
+    // Content messages carry the id of the pane they came from
+    ContentAreaMessage(i64, content::Message),
+    ContentHeaderMessage(i64, header::Message),
     HeaderMessage(header::Message),
+    ReplaceMessage(replace::Message),
+    CheckMessage(check::Message),
+    TagsMessage(tags::Message),
+    MetadataMessage(metadata::Message),
+    GraphMessage(graph::Message),
+    DailyMessage(daily::Message),
+    // Files may have changed outside of the app, checked when the window
+    // is focused and every few seconds
+    WindowFocused,
+    PollFiles,
+    // The session is saved before the window closes
+    CloseRequested(window::Id),
+    DismissWarning,
+    ModifiersChanged(keyboard::Modifiers),
 }
 
 pub struct Layout {
     // Metadata
     settings: Settings,
+    // Why the settings couldn't be read, shown until it's dismissed
+    settings_warning: Option<String>,
 
-    // Pane handling 
+    // Pane handling
     panes: pane_grid::State<Pane>,
     focus: Option<pane_grid::Pane>,
     menu_pane: Option<pane_grid::Pane>,
+    graph_pane: Option<pane_grid::Pane>,
+    // The content pane files open in when no content pane has focus
     content_pane: pane_grid::Pane,
+    next_pane_id: i64,
+    // Of the split between the menu and everything else, kept while the menu is closed
+    menu_ratio: f32,
 
     // Components
     filetree: FileTree,
-    content: ContentArea,
+    // Keyed by the id of the pane showing them
+    contents: BTreeMap<i64, ContentArea>,
     menu_header: MenuHeader,
     content_header: ContentHeader,
+    replace: ReplacePanel,
+    check: CheckPanel,
+    tags: TagBrowser,
+    calendar: Calendar,
+    menu_view: MenuView,
+    graph: GraphView,
 
     // App level data
     typst: TypstContext,
+    index: LinkIndex,
+    metadata: MetadataStore,
+    // Backlinks of the notes showing in each pane, worked out when the index
+    // or the open notes change rather than every frame
+    backlinks: HashMap<PathBuf, Vec<Backlink>>,
 }
 
+// The menu pane has id 0 and the graph pane -1, every other pane is a
+// content pane
 #[derive(Clone, Copy)]
 struct Pane {
     id: i64,
 }
 
+// What the menu pane is showing
+#[derive(Clone, Copy, PartialEq)]
+enum MenuView {
+    FileTree,
+    Replace,
+    Check,
+    Calendar,
+}
+
+const MENU_PANE_ID: i64 = 0;
+const GRAPH_PANE_ID: i64 = -1;
 const MIN_RATIO: f32 = 0.2;
 const MAX_RATIO: f32 = 0.8;
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
 
 fn pick_dir() -> Option<PathBuf> {
     let mut dialog = FileDialog::new()
@@ -61,40 +127,491 @@ fn pick_dir() -> Option<PathBuf> {
     dialog.pick_folder()
 }
 
+fn confirm_create_note(target: &str) -> bool {
+    let answer = MessageDialog::new()
+        .set_title("Note not found")
+        .set_description(format!("'{}' doesn't exist yet, create it?", target))
+        .set_buttons(MessageButtons::YesNo)
+        .show();
+    answer == MessageDialogResult::Yes
+}
+
+fn confirm_rename(summary: &str) -> bool {
+    let answer = MessageDialog::new()
+        .set_title("Rename")
+        .set_description(summary)
+        .set_buttons(MessageButtons::OkCancel)
+        .show();
+    answer == MessageDialogResult::Ok
+}
+
+// Ticks while the app runs so changes made by other programs show up
+// without refocusing the window. There's no async runtime for a timer, so a
+// thread sleeps between ticks and stops once the subscription is dropped.
+fn poll_files() -> mpsc::UnboundedReceiver<Message> {
+    let (sender, receiver) = mpsc::unbounded();
+    thread::spawn(move || {
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            if sender.unbounded_send(Message::PollFiles).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn show_error(title: &str, error: impl std::fmt::Display) {
+    MessageDialog::new()
+        .set_title(title)
+        .set_description(error.to_string())
+        .set_level(rfd::MessageLevel::Error)
+        .set_buttons(MessageButtons::Ok)
+        .show();
+}
+
 impl Layout {
     // TODO: think about how to handle errors when setting up the app
     fn new() -> Self {
 
         // Init app data
-        let settings = Settings::read().unwrap();
-        let typst = TypstContext::new().expect("Couldn't create temporary directory");
+        let (settings, settings_warning) = Settings::load();
+        let mut typst = TypstContext::new().expect("Couldn't create temporary directory");
+        typst.raster_fallback = settings.raster_preview.unwrap_or(true);
+        typst.dark = settings.dark_mode.unwrap_or(false);
         let filetree = FileTree::new(&settings);
-
-        // Init Panes 
-        let (mut panes, pane) = pane_grid::State::new(Pane{id: 0});
+        let index = LinkIndex::new(settings.root_dir.as_deref().map(Path::new));
+        let mut tags = TagBrowser::new();
+        tags.rebuild(&index);
+        let mut calendar = Calendar::new();
+        calendar.rebuild(&settings);
+        let metadata = MetadataStore::new(settings.root_dir.as_deref().map(Path::new));
+
+        // Init Panes
+        let (mut panes, pane) = pane_grid::State::new(Pane{id: MENU_PANE_ID});
         let (content_pane, menu_content_split) = panes.split(Axis::Vertical, pane, Pane{id: 1}).unwrap();
         let menu_pane = Some(pane);
-        panes.resize(menu_content_split, 0.25);
+        panes.resize(menu_content_split, session::DEFAULT_MENU_RATIO);
 
         Layout {
             // TODO error handling
             settings: settings,
+            settings_warning,
             panes,
             focus: None,
             menu_pane,
+            graph_pane: None,
             content_pane: content_pane,
+            next_pane_id: 2,
+            menu_ratio: session::DEFAULT_MENU_RATIO,
             filetree: filetree,
-            content: ContentArea::new(),
+            contents: BTreeMap::from([(1, ContentArea::new())]),
             menu_header: MenuHeader::new(),
             content_header: ContentHeader::new(true),
+            replace: ReplacePanel::new(),
+            check: CheckPanel::new(),
+            tags,
+            calendar,
+            menu_view: MenuView::FileTree,
+            graph: GraphView::new(),
 
             typst: typst,
+            index,
+            metadata,
+            backlinks: HashMap::new(),
+        }
+    }
+
+    // The id of the content pane with focus, falling back to the default one
+    fn focused_content(&self) -> i64 {
+        self.focus
+            .and_then(|pane| self.panes.get(pane))
+            .filter(|pane| self.contents.contains_key(&pane.id))
+            .or_else(|| self.panes.get(self.content_pane))
+            .map(|pane| pane.id)
+            .unwrap_or(1)
+    }
+
+    fn grid_pane(&self, id: i64) -> Option<pane_grid::Pane> {
+        self.panes.iter()
+            .find(|(_, pane)| pane.id == id)
+            .map(|(grid_pane, _)| *grid_pane)
+    }
+
+    fn update_content(&mut self, id: i64, message: content::Message) -> Task<Message> {
+        let Some(content) = self.contents.get_mut(&id) else {
+            return Task::none();
+        };
+        let task = content.update(message, &self.typst)
+            .map(move |message| Message::ContentAreaMessage(id, message));
+        self.cache_backlinks();
+        task
+    }
+
+    // Looks up backlinks for any note newly showing in a pane
+    fn cache_backlinks(&mut self) {
+        for content in self.contents.values() {
+            if let Some(doc) = content.active_document()
+                && !self.backlinks.contains_key(&doc.path)
+            {
+                self.backlinks.insert(doc.path.clone(), self.index.backlinks(&doc.path));
+            }
+        }
+    }
+
+    // Work that needs doing once the app has started
+    pub fn boot(&mut self) -> Task<Message> {
+        self.show_titles();
+        let restore = self.restore_session();
+        Task::batch([restore, self.index_changed()])
+    }
+
+    // Put the workspace back the way it was left, a missing or unreadable
+    // session starts from the defaults
+    fn restore_session(&mut self) -> Task<Message> {
+        let session = Session::read()
+            .unwrap_or_else(|_| Session::default())
+            .for_vault(self.settings.root_dir.as_deref());
+
+        self.filetree.expand_dirs(&session.expanded_dirs);
+        self.menu_ratio = session.menu_ratio.clamp(MIN_RATIO, MAX_RATIO);
+        if let Node::Split { id, .. } = self.panes.layout() {
+            let split = *id;
+            self.panes.resize(split, self.menu_ratio);
+        }
+        if !session.menu_open {
+            self.update(Message::HeaderMessage(header::Message::CloseMenu));
+        }
+
+        let id = self.focused_content();
+        let Some(content) = self.contents.get_mut(&id) else {
+            return Task::none();
+        };
+        let restore = content.restore(&session.tabs, session.active, &self.typst);
+        content.editor_open = session.editor_open;
+        content.preview_open = session.preview_open;
+        content.outline_open = session.outline_open;
+        restore.map(move |message| Message::ContentAreaMessage(id, message))
+    }
+
+    // The main content pane is what gets restored, notes open in other
+    // panes are added to its tabs so none are lost
+    fn session(&self) -> Session {
+        let main = self.contents.get(&self.focused_content());
+        let mut tabs = main.map(|content| content.tab_sessions()).unwrap_or_default();
+        for content in self.contents.values() {
+            for tab in content.tab_sessions() {
+                if !tabs.iter().any(|existing| existing.path == tab.path) {
+                    tabs.push(tab);
+                }
+            }
+        }
+        Session {
+            root_dir: self.settings.root_dir.clone(),
+            tabs,
+            active: main.and_then(|content| content.tabs().iter().position(|tab| tab.active)),
+            menu_open: self.menu_pane.is_some(),
+            menu_ratio: self.menu_ratio,
+            editor_open: main.is_some_and(|content| content.editor_open),
+            preview_open: main.is_none_or(|content| content.preview_open),
+            outline_open: main.is_some_and(|content| content.outline_open),
+            expanded_dirs: self.filetree.expanded_dirs(),
+        }
+    }
+
+    // Keep anything built from the notes up to date, metadata is extracted
+    // in the background
+    fn index_changed(&mut self) -> Task<Message> {
+        let graph = match self.graph_pane {
+            Some(_) => self.graph.rebuild(&self.index).map(Message::GraphMessage),
+            None => Task::none(),
+        };
+        self.tags.rebuild(&self.index);
+        self.backlinks.clear();
+        self.cache_backlinks();
+        self.calendar.rebuild(&self.settings);
+        if let Some(root) = &self.settings.root_dir {
+            self.filetree.set_templates(templates::list_templates(Path::new(root)));
+        }
+        self.filter_by_tag();
+        Task::batch([graph, self.metadata.refresh().map(Message::MetadataMessage)])
+    }
+
+    fn show_titles(&mut self) {
+        let titles = self.metadata.titles()
+            .map(|(path, title)| (path.clone(), title.to_string()))
+            .collect();
+        self.filetree.set_titles(titles);
+    }
+
+    fn filter_by_tag(&mut self) {
+        let filter = self.tags.selected().map(|tag| self.index.notes_tagged(tag));
+        self.filetree.set_filter(filter);
+    }
+
+    // Switching to the view that's already showing goes back to the file tree
+    fn toggle_menu_view(&mut self, view: MenuView) {
+        self.menu_view = if self.menu_view == view { MenuView::FileTree } else { view };
+    }
+
+    fn toggle_graph(&mut self) -> Task<Message> {
+        if let Some(graph_pane) = self.graph_pane.take() {
+            self.panes.close(graph_pane);
+            self.focus = None;
+            return Task::none();
+        }
+        let Some((graph_pane, _)) = self.panes.split(Axis::Vertical, self.content_pane, Pane{id: GRAPH_PANE_ID}) else {
+            return Task::none();
+        };
+        self.graph_pane = Some(graph_pane);
+        self.graph.rebuild(&self.index).map(Message::GraphMessage)
+    }
+
+    // When the same file is open in several panes, repeat an edit made in
+    // one of them in the others. Undo, redo and saving replace the text.
+    fn sync_panes(&mut self, source: i64, edit: Option<(text_editor::Cursor, text_editor::Action)>) {
+        let Some(doc) = self.contents.get(&source).and_then(|content| content.active_document()) else {
+            return;
+        };
+        let path = doc.path.clone();
+        let others_open = self.contents.iter()
+            .any(|(id, content)| *id != source && content.has_document(&path));
+        if !others_open {
+            return;
+        }
+
+        if let Some((cursor, action)) = edit {
+            for (_, content) in self.contents.iter_mut().filter(|(id, _)| **id != source) {
+                content.apply_edit(&path, cursor, &action);
+            }
+            return;
+        }
+        let text = doc.text();
+        let dirty = doc.is_dirty();
+        for (_, content) in self.contents.iter_mut().filter(|(id, _)| **id != source) {
+            content.sync_document(&path, &text, dirty);
+        }
+    }
+
+    // Follow a link to another note, offering to create it if it doesn't exist
+    fn open_note(&mut self, id: i64, target: &str) -> Task<Message> {
+        let Some(root) = self.settings.root_dir.as_ref().map(PathBuf::from) else {
+            return Task::none();
+        };
+        let Some(path) = links::resolve(&root, target) else {
+            show_error("Can't follow link", format!("'{}' is outside of the vault", target));
+            return Task::none();
+        };
+        if !path.exists() {
+            if !confirm_create_note(target) || links::create_note(&path).is_err() {
+                return Task::none();
+            }
+            self.filetree.refresh(&self.settings);
+            self.index.update_file(&path);
+            let refresh = self.index_changed();
+            return Task::batch([refresh, self.update_content(id, content::Message::OpenFile(path))]);
+        }
+        self.update_content(id, content::Message::OpenFile(path))
+    }
+
+    // Open the daily note for a date, creating it from the template if it
+    // doesn't exist yet
+    fn open_daily(&mut self, date: Date) -> Task<Message> {
+        let Some(path) = daily::note_path(&self.settings, date) else {
+            return Task::none();
+        };
+        self.calendar.show_month(date);
+        let id = self.focused_content();
+        if path.exists() {
+            return self.update_content(id, content::Message::OpenFile(path));
+        }
+        let (line, column) = match daily::create_note(&self.settings, &path, date) {
+            Ok(cursor) => cursor.unwrap_or_default(),
+            Err(err) => {
+                show_error("Couldn't create daily note", err);
+                return Task::none();
+            }
+        };
+        self.filetree.refresh(&self.settings);
+        self.index.update_file(&path);
+        let refresh = self.index_changed();
+        Task::batch([refresh, self.update_content(id, content::Message::OpenFileAt(path, line, column))])
+    }
+
+    // Create the note typed into the file tree from the chosen template
+    fn create_note(&mut self) -> Task<Message> {
+        let Some(root) = self.settings.root_dir.as_ref().map(PathBuf::from) else {
+            return Task::none();
+        };
+        let Some((note, choice)) = self.filetree.new_note_request() else {
+            return Task::none();
+        };
+        let (path, (line, column)) = match templates::create_note(&root, &self.settings, &note, &choice) {
+            Ok((path, cursor)) => (path, cursor.unwrap_or_default()),
+            Err(err) => {
+                show_error("Couldn't create note", err);
+                return Task::none();
+            }
+        };
+
+        self.filetree.update(filetree::Message::CancelNewNote);
+        self.filetree.refresh(&self.settings);
+        self.index.update_file(&path);
+        let refresh = self.index_changed();
+        let id = self.focused_content();
+        Task::batch([refresh, self.update_content(id, content::Message::OpenFileAt(path, line, column))])
+    }
+
+    // Days step from the daily note being edited, or from today
+    fn step_daily(&mut self, days: i64) -> Task<Message> {
+        let current = self.contents.get(&self.focused_content())
+            .and_then(|content| content.active_document())
+            .and_then(|doc| daily::note_date(&self.settings, &doc.path))
+            .unwrap_or_else(Date::today);
+        self.open_daily(current.add_days(days))
+    }
+
+    // Move a file to the path typed into the file tree, updating every
+    // reference to it once the changes are confirmed
+    fn rename_file(&mut self) -> Task<Message> {
+        let Some(root) = self.settings.root_dir.as_ref().map(PathBuf::from) else {
+            return Task::none();
+        };
+        let Some((from, input)) = self.filetree.rename_request() else {
+            return Task::none();
+        };
+        let mut to = root.join("typst").join(input.trim().trim_start_matches('/'));
+        if to.extension().is_none()
+            && let Some(extension) = from.extension()
+        {
+            to.set_extension(extension);
+        }
+        if to == from {
+            self.filetree.update(filetree::Message::CancelRename);
+            return Task::none();
+        }
+
+        let plan = match RenamePlan::new(&root, &from, &to) {
+            Ok(plan) => plan,
+            Err(err) => {
+                show_error("Couldn't rename", err);
+                return Task::none();
+            }
+        };
+        let mut files = plan.touched_files();
+        files.push(plan.from.clone());
+        if self.has_unsaved(&files) || !confirm_rename(&plan.summary(&root)) {
+            return Task::none();
+        }
+        if let Err(err) = plan.apply() {
+            show_error("Couldn't rename", err);
+            return Task::none();
+        }
+
+        self.filetree.update(filetree::Message::CancelRename);
+        self.filetree.refresh(&self.settings);
+        for content in self.contents.values_mut() {
+            content.rename_document(&plan.from, &plan.to);
+        }
+        self.index.refresh();
+        self.reload_files(&plan.touched_files())
+    }
+
+    // Files are rewritten on disk by replacing and renaming, which would lose
+    // unsaved edits to any of them that are open. Returns true if there are
+    // some, after asking for them to be saved first.
+    fn has_unsaved(&self, files: &[PathBuf]) -> bool {
+        let unsaved: Vec<String> = files.iter()
+            .filter(|file| self.contents.values().any(|content| content.has_unsaved(file)))
+            .map(|file| file.display().to_string())
+            .collect();
+        if !unsaved.is_empty() {
+            show_error("Unsaved changes", format!("Save these notes first:\n{}", unsaved.join("\n")));
+        }
+        !unsaved.is_empty()
+    }
+
+    // Asks about every note with unsaved changes before anything is closed,
+    // returns false if quitting was cancelled
+    fn confirm_quit(&mut self) -> bool {
+        let mut unsaved: Vec<PathBuf> = self.contents.values()
+            .flat_map(|content| content.unsaved_documents())
+            .collect();
+        unsaved.sort();
+        unsaved.dedup();
+        for path in unsaved {
+            let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let answer = MessageDialog::new()
+                .set_title("Unsaved changes")
+                .set_description(format!("Save changes to {}?", name))
+                .set_buttons(MessageButtons::YesNoCancel)
+                .show();
+            match answer {
+                MessageDialogResult::Yes => {
+                    for content in self.contents.values_mut() {
+                        if let Err(err) = content.save_document(&path) {
+                            show_error("Couldn't save", err);
+                            return false;
+                        }
+                    }
+                }
+                MessageDialogResult::No => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    fn split(&mut self, id: i64, axis: Axis) -> Task<Message> {
+        let Some(grid_pane) = self.grid_pane(id) else {
+            return Task::none();
+        };
+        let new_id = self.next_pane_id;
+        let Some((new_pane, _)) = self.panes.split(axis, grid_pane, Pane{id: new_id}) else {
+            return Task::none();
+        };
+        self.next_pane_id += 1;
+        self.focus = Some(new_pane);
+
+        // Start the new pane on the same note so it can be viewed in two places
+        let content = ContentArea::new();
+        let open_file = self.contents.get(&id)
+            .and_then(|content| content.active_document())
+            .map(|doc| doc.path.clone());
+        self.contents.insert(new_id, content);
+        match open_file {
+            Some(path) => self.update_content(new_id, content::Message::OpenFile(path)),
+            None => Task::none(),
+        }
+    }
+
+    fn close_pane(&mut self, id: i64) {
+        if self.contents.len() < 2 {
+            return;
+        }
+        let Some(grid_pane) = self.grid_pane(id) else {
+            return;
+        };
+        if let Some(content) = self.contents.get_mut(&id)
+            && !content.close_all(&self.typst)
+        {
+            return;
+        }
+
+        self.contents.remove(&id);
+        self.panes.close(grid_pane);
+        self.focus = None;
+        if self.content_pane == grid_pane {
+            let next = self.contents.keys().next().copied().unwrap_or(1);
+            if let Some(pane) = self.grid_pane(next) {
+                self.content_pane = pane;
+            }
         }
     }
 
     pub fn update(&mut self, message: Message) -> Task<Message> {
         match message {
-            Message::PaneClicked(pane) => { 
+            Message::PaneClicked(pane) => {
                 self.focus = Some(pane);
                 Task::none()
             }
@@ -102,17 +619,32 @@ impl Layout {
             Message::PaneResized(pane_grid::ResizeEvent { split, ratio }) => {
                 if ratio > MIN_RATIO && ratio < MAX_RATIO {
                     self.panes.resize(split, ratio);
+                    // The menu is always split off from the whole window
+                    if self.menu_pane.is_some()
+                        && let Node::Split { id, .. } = self.panes.layout()
+                        && *id == split
+                    {
+                        self.menu_ratio = ratio;
+                    }
                 }
                 Task::none()
             }
 
             Message::FiletreeMessage(filetree::Message::OpenFile(filepath)) => {
                 self.filetree.update(filetree::Message::OpenFile(filepath.clone()));
-                self.content.update(content::Message::OpenFile(filepath), &mut self.typst)
-                    .map(|future| Message::ContentAreaMessage(future))
+                let id = self.focused_content();
+                self.update_content(id, content::Message::OpenFile(filepath))
+            }
+
+            Message::FiletreeMessage(filetree::Message::SubmitRename) => {
+                self.rename_file()
+            }
+
+            Message::FiletreeMessage(filetree::Message::SubmitNewNote) => {
+                self.create_note()
             }
 
-            Message::FiletreeMessage(message) => { 
+            Message::FiletreeMessage(message) => {
                 self.filetree.update(message);
                 Task::none()
             }
@@ -128,63 +660,309 @@ impl Layout {
 
             Message::HeaderMessage(header::Message::OpenMenu)  => {
                 if self.menu_pane.is_none() {
-                    let (menu_pane, menu_content_split) = 
-                        self.panes.split(Axis::Vertical, self.content_pane, Pane{id: 0}).unwrap();
+                    let (menu_pane, _) =
+                        self.panes.split(Axis::Vertical, self.content_pane, Pane{id: MENU_PANE_ID}).unwrap();
                     self.menu_pane = Some(menu_pane);
                     self.content_header.update(header::Message::OpenMenu);
-                    self.panes.resize(menu_content_split, 0.25);
 
-                    // TODO there must be a better way to do this
-                    self.panes.swap(menu_pane, self.content_pane);
+                    // The menu spans the whole height whichever pane it was split from
+                    self.panes.move_to_edge(menu_pane, Edge::Left);
+                    if let Node::Split { id, .. } = self.panes.layout() {
+                        let split = *id;
+                        self.panes.resize(split, self.menu_ratio);
+                    }
                 }
                 Task::none()
             }
 
             Message::HeaderMessage(header::Message::OpenDirectory) => {
-                if let Some(dir) = pick_dir() {
-                    self.filetree.open_dir(dir, &mut self.settings);
-                }
+                let Some(dir) = pick_dir() else {
+                    return Task::none();
+                };
+                self.filetree.open_dir(dir, &mut self.settings);
+                let root_dir = self.settings.root_dir.as_deref().map(Path::new);
+                self.index = LinkIndex::new(root_dir);
+                self.metadata = MetadataStore::new(root_dir);
+                self.show_titles();
+                self.index_changed()
+            }
+
+            Message::HeaderMessage(header::Message::ToggleReplace) => {
+                self.toggle_menu_view(MenuView::Replace);
                 Task::none()
             }
 
-            Message::HeaderMessage(header::Message::TogglePreview) => {
-                self.content.preview_open = !self.content.preview_open;
+            Message::HeaderMessage(header::Message::ToggleCheck) => {
+                self.toggle_menu_view(MenuView::Check);
                 Task::none()
             }
 
-            Message::HeaderMessage(header::Message::ToggleEditor) => {
-                self.content.editor_open = !self.content.editor_open;
+            Message::HeaderMessage(header::Message::ToggleGraph) => {
+                self.toggle_graph()
+            }
+
+            Message::HeaderMessage(header::Message::ToggleCalendar) => {
+                self.toggle_menu_view(MenuView::Calendar);
                 Task::none()
             }
 
+            Message::HeaderMessage(header::Message::OpenToday) => {
+                self.open_daily(Date::today())
+            }
+
+            Message::HeaderMessage(header::Message::ToggleDarkMode) => {
+                let dark = !self.typst.dark;
+                self.typst.dark = dark;
+                self.settings.dark_mode = Some(dark);
+                self.settings.write();
+                Task::batch(self.contents.iter_mut().map(|(id, content)| {
+                    let id = *id;
+                    content.render_all(&self.typst)
+                        .map(move |message| Message::ContentAreaMessage(id, message))
+                }))
+            }
+
             Message::HeaderMessage(message) => { todo!() }
 
-            Message::ContentAreaMessage(message) => {
-                self.content.update(message, &mut self.typst)
-                    .map(|future| Message::ContentAreaMessage(future))
+            // The content header buttons act on the pane they're in
+            Message::ContentHeaderMessage(id, header::Message::TogglePreview) => {
+                if let Some(content) = self.contents.get_mut(&id) {
+                    content.preview_open = !content.preview_open;
+                }
+                Task::none()
+            }
+
+            Message::ContentHeaderMessage(id, header::Message::ToggleOutline) => {
+                if let Some(content) = self.contents.get_mut(&id) {
+                    content.outline_open = !content.outline_open;
+                }
+                Task::none()
+            }
+
+            Message::ContentHeaderMessage(id, header::Message::ToggleEditor) => {
+                if let Some(content) = self.contents.get_mut(&id) {
+                    content.editor_open = !content.editor_open;
+                }
+                Task::none()
+            }
+
+            Message::ContentHeaderMessage(id, header::Message::Tab(message)) => {
+                self.update_content(id, content::Message::Tab(message))
+            }
+
+            Message::ContentHeaderMessage(id, header::Message::Zoom(message)) => {
+                self.update_content(id, content::Message::Zoom(message))
+            }
+
+            Message::ContentHeaderMessage(id, header::Message::SplitPane(axis)) => {
+                self.split(id, axis)
+            }
+
+            Message::ContentHeaderMessage(id, header::Message::ClosePane) => {
+                self.close_pane(id);
+                Task::none()
+            }
+
+            Message::ContentHeaderMessage(_, message) => {
+                self.update(Message::HeaderMessage(message))
+            }
+
+            Message::ReplaceMessage(replace::Message::Apply) => {
+                if self.has_unsaved(&self.replace.pending_files()) {
+                    return Task::none();
+                }
+                let touched = self.replace.apply();
+                self.reload_files(&touched)
+            }
+
+            Message::ReplaceMessage(replace::Message::Undo) => {
+                if self.has_unsaved(&self.replace.undo_files()) {
+                    return Task::none();
+                }
+                let touched = self.replace.undo();
+                self.reload_files(&touched)
+            }
+
+            Message::ReplaceMessage(message) => {
+                self.replace.update(message, &self.settings);
+                Task::none()
+            }
+
+            Message::CheckMessage(check::Message::OpenIssue(filepath, line)) => {
+                let id = self.focused_content();
+                self.update_content(id, content::Message::OpenFileAt(filepath, line, 0))
             }
+
+            Message::TagsMessage(message) => {
+                self.tags.update(message);
+                self.filter_by_tag();
+                Task::none()
+            }
+
+            Message::CheckMessage(message) => {
+                self.check.update(message, &self.settings);
+                Task::none()
+            }
+
+            Message::DailyMessage(daily::Message::Open(date)) => self.open_daily(date),
+            Message::DailyMessage(daily::Message::Today) => self.open_daily(Date::today()),
+            Message::DailyMessage(daily::Message::PreviousDay) => self.step_daily(-1),
+            Message::DailyMessage(daily::Message::NextDay) => self.step_daily(1),
+
+            Message::DailyMessage(message) => {
+                self.calendar.update(message);
+                Task::none()
+            }
+
+            Message::GraphMessage(graph::Message::OpenFile(filepath)) => {
+                self.update(Message::FiletreeMessage(filetree::Message::OpenFile(filepath)))
+            }
+
+            Message::GraphMessage(message) => {
+                self.graph.update(message);
+                Task::none()
+            }
+
+            Message::WindowFocused | Message::PollFiles => {
+                let changed = self.index.refresh();
+                if changed.is_empty() {
+                    return Task::none();
+                }
+                self.filetree.refresh(&self.settings);
+                self.reload_files(&changed)
+            }
+
+            Message::CloseRequested(window) => {
+                if !self.confirm_quit() {
+                    return Task::none();
+                }
+                if let Err(err) = self.session().write() {
+                    show_error("Couldn't save the session", err);
+                }
+                window::close(window)
+            }
+
+            Message::DismissWarning => {
+                self.settings_warning = None;
+                Task::none()
+            }
+
+            Message::ModifiersChanged(modifiers) => {
+                for content in self.contents.values_mut() {
+                    content.set_modifiers(modifiers);
+                }
+                Task::none()
+            }
+
+            Message::MetadataMessage(message) => {
+                self.metadata.update(message);
+                self.show_titles();
+                Task::none()
+            }
+
+            Message::ContentAreaMessage(id, content::Message::OpenNote(target)) => {
+                self.open_note(id, &target)
+            }
+
+            Message::ContentAreaMessage(id, message) => {
+                let replaced = matches!(
+                    message,
+                    content::Message::Undo | content::Message::Redo | content::Message::Save
+                );
+                let saved = matches!(message, content::Message::Save);
+                // Edits are repeated in other panes from where they were made
+                let edit = match &message {
+                    content::Message::Edit(action) if action.is_edit() => self.contents.get(&id)
+                        .and_then(|content| content.active_document())
+                        .map(|doc| (doc.cursor(), action.clone())),
+                    _ => None,
+                };
+                let task = self.update_content(id, message);
+                if edit.is_some() || replaced {
+                    self.sync_panes(id, edit);
+                }
+                if saved && let Some(doc) = self.contents.get(&id).and_then(|content| content.active_document()) {
+                    let path = doc.path.clone();
+                    self.index.update_file(&path);
+                    return Task::batch([task, self.index_changed()]);
+                }
+                task
+            }
+        }
+    }
+
+    fn reload_files(&mut self, files: &[PathBuf]) -> Task<Message> {
+        for file in files {
+            self.index.update_file(file);
         }
+        let mut tasks = vec![self.index_changed()];
+        tasks.extend(self.contents.iter_mut()
+            .map(|(id, content)| {
+                let id = *id;
+                content.reload_files(files, &self.typst)
+                    .map(move |message| Message::ContentAreaMessage(id, message))
+            }));
+        Task::batch(tasks)
+    }
+
+    pub fn theme(&self) -> Theme {
+        if self.typst.dark { Theme::Dark } else { Theme::Light }
+    }
+
+    pub fn subscription(&self) -> Subscription<Message> {
+        let events = event::listen_with(|event, _status, window| match event {
+            Event::Window(window::Event::Focused) => Some(Message::WindowFocused),
+            Event::Window(window::Event::CloseRequested) => Some(Message::CloseRequested(window)),
+            Event::Keyboard(keyboard::Event::ModifiersChanged(modifiers)) => Some(Message::ModifiersChanged(modifiers)),
+            _ => None,
+        });
+        Subscription::batch([events, Subscription::run(poll_files)])
     }
 
     pub fn view(&self) -> Element<'_, Message> {
-        //let focus = self.focus;
+        let focused_content = self.focused_content();
+        let can_close = self.contents.len() > 1;
 
         let pane_grid = PaneGrid::new(&self.panes, |_id, pane, _is_maximized| {
-            //let is_focused = focus == Some(id);
+            let id = pane.id;
 
             pane_grid::Content::new(responsive(move |_| {
-                if pane.id == 0 {
+                if id == MENU_PANE_ID {
+                    let menu_content = match self.menu_view {
+                        MenuView::FileTree if !self.tags.is_empty() => column![
+                            self.tags.view().map(Message::TagsMessage),
+                            components::hrule(),
+                            self.filetree.view().map(Message::FiletreeMessage),
+                        ]
+                        .into(),
+                        MenuView::FileTree => self.filetree.view().map(Message::FiletreeMessage),
+                        MenuView::Replace => self.replace.view().map(Message::ReplaceMessage),
+                        MenuView::Check => self.check.view().map(Message::CheckMessage),
+                        MenuView::Calendar => self.calendar.view().map(Message::DailyMessage),
+                    };
                     column! [
                         self.menu_header.view().map(Message::HeaderMessage),
-                        self.filetree.view().map(Message::FiletreeMessage),
+                        menu_content,
                     ]
                     .into()
                 }
+                else if id == GRAPH_PANE_ID {
+                    self.graph.view().map(Message::GraphMessage)
+                }
                 else {
+                    let Some(content) = self.contents.get(&id) else {
+                        return column![].into();
+                    };
+                    let backlinks = content.active_document()
+                        .and_then(|doc| self.backlinks.get(&doc.path))
+                        .map(Vec::as_slice)
+                        .unwrap_or_default();
                     column![
-                        self.content_header.view().map(Message::HeaderMessage),
+                        self.content_header
+                            .view(content.tabs(), id == focused_content, can_close)
+                            .map(move |message| Message::ContentHeaderMessage(id, message)),
                         container(
-                            self.content.view().map(Message::ContentAreaMessage)
+                            content.view(backlinks).map(move |message| Message::ContentAreaMessage(id, message))
                         )
                     ]
                     .into()
@@ -197,7 +975,24 @@ impl Layout {
         .on_click(Message::PaneClicked)
         .on_resize(10, Message::PaneResized);
 
-        container(pane_grid).into()
+        let Some(warning) = &self.settings_warning else {
+            return container(pane_grid).into();
+        };
+        column![
+            container(
+                row![
+                    text(warning).width(Fill),
+                    button("Dismiss").on_press(Message::DismissWarning),
+                ]
+                .spacing(styles::SPACING_SMALL)
+                .align_y(Alignment::Center)
+            )
+            .padding(styles::SPACING_SMALL)
+            .width(Fill)
+            .style(container::warning),
+            pane_grid,
+        ]
+        .into()
     }
 }
 
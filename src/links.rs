@@ -0,0 +1,412 @@
+#![allow(dead_code, unused)]
+
+// Links between notes. A note links to another with the bundled
+// `#note("path/to/note")` function, or the `[[path/to/note]]` and
+// `[[path/to/note|label]]` shorthands which are rewritten into calls to it
+// before compiling. Targets are relative to the vault's typst directory and
+// the `.typ` extension is optional.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use miniserde::{json, Deserialize};
+
+use crate::error::FileSystemError;
+use crate::rename;
+
+// Links to notes use this scheme so they can be told apart from urls
+pub const NOTE_SCHEME: &str = "memristor:";
+
+// Where link metadata is attached in the compiled document
+pub const LINK_LABEL: &str = "<memristor-link>";
+
+// Prepended to every note before compiling, it defines #note and records
+// where links, headings and marked lines end up. It's kept to a single line so line
+// numbers in the compiled source are offset by exactly one.
+pub const PRELUDE: &str = concat!(
+    "#let note(target, ..body) = link(\"memristor:\" + target, body.pos().at(0, default: target)); ",
+    "#show link: it => context { let p = here().position(); let s = measure(it); ",
+    "let t = if type(it.dest) == label { locate(it.dest).position() } else if type(it.dest) == location { it.dest.position() } ",
+    "else if type(it.dest) == dictionary { it.dest } else { none }; ",
+    "[#metadata((dest: if type(it.dest) == str { it.dest } else { repr(it.dest) }, ",
+    "page: p.page, x: p.x.pt(), y: p.y.pt(), w: s.width.pt(), h: s.height.pt(), ",
+    "tp: if t == none { none } else { t.page }, ty: if t == none { none } else { t.y.pt() })) <memristor-link>] + it }; ",
+    "#show heading: it => context { let p = here().position(); ",
+    "[#metadata((page: p.page, y: p.y.pt())) <memristor-heading>] + it }; ",
+    "#let memristor-line(n) = context { let p = here().position(); ",
+    "[#metadata((line: n, page: p.page, y: p.y.pt())) <memristor-line>] }\n",
+);
+
+pub const PRELUDE_LINES: usize = 1;
+
+// Put in front of the prelude in dark mode, on the same line so line numbers
+// don't move. Notes which set their own colours keep them.
+pub const DARK_PRELUDE: &str = "#set page(fill: rgb(\"#1e1f22\")); #set text(fill: rgb(\"#dcdcdc\")); ";
+
+// Where a link ended up in the compiled document, in points
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct LinkRegion {
+    pub dest: String,
+    pub page: usize,
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+    // Where links to labels and locations point, in points
+    pub tp: Option<usize>,
+    pub ty: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkTarget<'a> {
+    Note(&'a str),
+    // A position in the same document, the page is one based
+    Internal { page: usize, y: f32 },
+    Url(&'a str),
+    // A file:// url is absolute, otherwise paths are relative to the note
+    // directory like the paths typst resolves
+    File { path: &'a str, absolute: bool },
+}
+
+impl LinkRegion {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        // Positions are approximate so give a little slack
+        const SLACK: f32 = 2.0;
+        x >= self.x - SLACK && x <= self.x + self.w + SLACK
+            && y >= self.y - SLACK && y <= self.y + self.h + SLACK
+    }
+
+    // The vault relative target if this links to another note
+    pub fn note_target(&self) -> Option<&str> {
+        self.dest.strip_prefix(NOTE_SCHEME)
+    }
+
+    pub fn target(&self) -> LinkTarget<'_> {
+        if let Some(target) = self.note_target() {
+            return LinkTarget::Note(target);
+        }
+        if let (Some(page), Some(y)) = (self.tp, self.ty) {
+            return LinkTarget::Internal { page, y };
+        }
+        if let Some(path) = self.dest.strip_prefix("file://") {
+            return LinkTarget::File { path, absolute: true };
+        }
+        let is_url = self.dest.contains("://")
+            || ["mailto:", "tel:"].iter().any(|scheme| self.dest.starts_with(scheme));
+        if is_url { LinkTarget::Url(&self.dest) } else { LinkTarget::File { path: &self.dest, absolute: false } }
+    }
+}
+
+pub fn parse_link_regions(query_output: &str) -> Vec<LinkRegion> {
+    json::from_str(query_output).unwrap_or_default()
+}
+
+// Opens a URL or file with whatever the system uses for it. Nothing goes
+// through a shell, on Windows cmd would treat & in a URL as a new command.
+pub fn open_external(target: &str) -> io::Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(target_os = "windows") {
+        let mut command = Command::new("rundll32");
+        command.arg("url.dll,FileProtocolHandler");
+        command
+    } else {
+        Command::new("xdg-open")
+    };
+    command.arg(target).spawn()?;
+    Ok(())
+}
+
+// Rewrites the [[...]] shorthand into #note calls and adds the prelude.
+// Nothing is rewritten inside raw text or line comments.
+pub fn preprocess(source: &str) -> String {
+    let mut output = String::with_capacity(PRELUDE.len() + source.len());
+    output.push_str(PRELUDE);
+
+    for (index, line) in source.split('\n').enumerate() {
+        if index > 0 {
+            output.push('\n');
+        }
+        output.push_str(&rewrite_line(line));
+    }
+    output
+}
+
+fn rewrite_line(line: &str) -> String {
+    let mut output = String::with_capacity(line.len());
+    let strings = string_ranges(line);
+    let mut in_raw = false;
+    let mut rest = line;
+
+    while let Some(c) = rest.chars().next() {
+        let offset = line.len() - rest.len();
+        if c == '`' {
+            in_raw = !in_raw;
+        }
+        if !in_raw && rest.starts_with("//") {
+            output.push_str(rest);
+            break;
+        }
+        if !in_raw && !in_string(&strings, offset) && rest.starts_with("[[") && let Some(end) = rest.find("]]") {
+            output.push_str(&note_call(&rest[2..end]));
+            rest = &rest[end + 2..];
+            continue;
+        }
+        output.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    output
+}
+
+// The byte ranges of the string literals in the code on a line. Quotes in
+// markup are just quotes, code starts at a # and carries on through its
+// brackets, or to the end of the line for keywords like #let.
+fn string_ranges(line: &str) -> Vec<(usize, usize)> {
+    let mut ranges = vec!();
+    // Open brackets, [ goes back to markup
+    let mut brackets: Vec<char> = vec!();
+    // The bracket depth a # expression or statement started at
+    let mut expression = None;
+    let mut statement = None;
+    let mut string_start = None;
+    let mut escaped = false;
+    let mut in_raw = false;
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((offset, c)) = chars.next() {
+        if let Some(start) = string_start {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => {
+                    ranges.push((start, offset + 1));
+                    string_start = None;
+                }
+                _ => {}
+            }
+            continue;
+        }
+        if c == '`' {
+            in_raw = !in_raw;
+        }
+        if in_raw {
+            continue;
+        }
+
+        let depth = brackets.len();
+        // An expression like #f(x)[y] ends at the first character which can't continue it
+        if expression == Some(depth) && !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '(' | '[' | '{')) {
+            expression = None;
+        }
+        let in_code = expression == Some(depth) || statement == Some(depth) || matches!(brackets.last(), Some('(' | '{'));
+        match c {
+            '"' if in_code => string_start = Some(offset),
+            '(' | '{' | '[' if in_code => brackets.push(c),
+            ')' | '}' if in_code => {
+                brackets.pop();
+            }
+            '[' if depth > 0 => brackets.push(c),
+            ']' if brackets.last() == Some(&'[') => {
+                brackets.pop();
+            }
+            '#' if !in_code => {
+                let mut name = String::new();
+                while let Some(&(_, c)) = chars.peek().filter(|(_, c)| c.is_alphanumeric() || *c == '_') {
+                    name.push(c);
+                    chars.next();
+                }
+                match name.as_str() {
+                    "let" | "set" | "show" | "import" | "include" => statement = Some(depth),
+                    _ => expression = Some(depth),
+                }
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
+
+fn in_string(ranges: &[(usize, usize)], offset: usize) -> bool {
+    ranges.iter().any(|&(start, end)| start <= offset && offset < end)
+}
+
+fn note_call(inner: &str) -> String {
+    let (target, label) = match inner.split_once('|') {
+        Some((target, label)) => (target.trim(), Some(label.trim())),
+        None => (inner.trim(), None),
+    };
+    let target = target.replace('\\', "\\\\").replace('"', "\\\"");
+    match label {
+        Some(label) => format!("#{{note(\"{}\", [{}])}}", target, label),
+        None => format!("#{{note(\"{}\")}}", target),
+    }
+}
+
+// A link to another note found in a note's source
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoteReference {
+    pub target: String,
+    // Zero based line and byte offset of the target in that line
+    pub line: usize,
+    pub column: usize,
+}
+
+// Finds every [[...]] shorthand and #note call in a note's source
+pub fn find_links(source: &str) -> Vec<NoteReference> {
+    let mut references = vec!();
+    for (line_number, line) in source.lines().enumerate() {
+        let strings = string_ranges(line);
+        let mut in_raw = false;
+        let mut offset = 0;
+        while offset < line.len() {
+            let rest = &line[offset..];
+            let c = rest.chars().next().unwrap();
+            if c == '`' {
+                in_raw = !in_raw;
+            }
+            if in_raw {
+                offset += c.len_utf8();
+                continue;
+            }
+            if rest.starts_with("//") {
+                break;
+            }
+
+            if !in_string(&strings, offset) && rest.starts_with("[[") && let Some(end) = rest.find("]]") {
+                let inner = &rest[2..end];
+                let target = inner.split('|').next().unwrap_or_default();
+                let leading = target.len() - target.trim_start().len();
+                references.push(NoteReference {
+                    target: target.trim().to_string(),
+                    line: line_number,
+                    column: offset + 2 + leading,
+                });
+                offset += end + 2;
+                continue;
+            }
+
+            // #note("...") or note("...") inside code, but not footnote("...")
+            let starts_word = !line[..offset].ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == '-');
+            if starts_word && rest.starts_with("note(\"") {
+                let start = offset + "note(\"".len();
+                if let Some(end) = line[start..].find('"') {
+                    references.push(NoteReference {
+                        target: line[start..start + end].to_string(),
+                        line: line_number,
+                        column: start,
+                    });
+                    offset = start + end + 1;
+                    continue;
+                }
+            }
+            offset += c.len_utf8();
+        }
+    }
+    references
+}
+
+// The note a link target refers to, None for targets which use .. to
+// leave the typst directory. Dots are part of the name, so .typ is added
+// to anything which doesn't already end in it.
+pub fn resolve(root_dir: &Path, target: &str) -> Option<PathBuf> {
+    let typst_dir = rename::normalize(&root_dir.join("typst"));
+    let mut path = rename::normalize(&typst_dir.join(target.trim_start_matches('/')));
+    if !path.starts_with(&typst_dir) || path == typst_dir {
+        return None;
+    }
+    if path.extension().is_none_or(|extension| extension != "typ") {
+        path.as_mut_os_string().push(".typ");
+    }
+    Some(path)
+}
+
+// The link target for a note, the inverse of resolve
+pub fn target_for(root_dir: &Path, note: &Path) -> Option<String> {
+    let relative = note.strip_prefix(root_dir.join("typst")).ok()?;
+    let target = relative.with_extension("");
+    Some(target.to_string_lossy().replace('\\', "/"))
+}
+
+// Creates an empty note, including any directories it should be in
+pub fn create_note(path: &Path) -> Result<(), FileSystemError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|_| FileSystemError::CreateDirError { path: parent.into() })?;
+    }
+    fs::File::create_new(path)
+        .map_err(|_| FileSystemError::CreateFileError { path: path.into() })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shorthand_is_rewritten() {
+        let output = preprocess("See [[projects/memristor]] and [[ideas|my ideas]].\n`[[raw]]` // [[comment]]");
+        let source = output.strip_prefix(PRELUDE).unwrap();
+        assert_eq!(
+            source,
+            "See #{note(\"projects/memristor\")} and #{note(\"ideas\", [my ideas])}.\n`[[raw]]` // [[comment]]"
+        );
+        assert_eq!(output.lines().count(), source.lines().count() + PRELUDE_LINES);
+
+        // Only strings in code are left alone, quotes in markup aren't strings
+        let source = "#let hint = \"see [[x]]\"\n#f(\"[[y]]\")[in \"[[z]]\"] and \"[[w]]\"";
+        let output = preprocess(source);
+        assert_eq!(
+            output.strip_prefix(PRELUDE).unwrap(),
+            "#let hint = \"see [[x]]\"\n#f(\"[[y]]\")[in \"#{note(\"z\")}\"] and \"#{note(\"w\")}\""
+        );
+        let targets: Vec<String> = find_links(source).into_iter().map(|reference| reference.target).collect();
+        assert_eq!(targets, vec!["z", "w"]);
+    }
+
+    #[test]
+    fn links_are_found() {
+        let source = "See [[ a/b | label]] and #note(\"c\")\n#footnote(\"no\") `[[raw]]` // [[comment]]\n#let x = note(\"d\", [D])";
+        let targets: Vec<(String, usize, usize)> = find_links(source).into_iter()
+            .map(|reference| (reference.target, reference.line, reference.column))
+            .collect();
+        assert_eq!(targets, vec![
+            ("a/b".to_string(), 0, 7),
+            ("c".to_string(), 0, 32),
+            ("d".to_string(), 2, 15),
+        ]);
+    }
+
+    #[test]
+    fn targets_resolve_within_the_vault() {
+        let root = Path::new("/vault");
+        assert_eq!(resolve(root, "dir/note"), Some(PathBuf::from("/vault/typst/dir/note.typ")));
+        assert_eq!(resolve(root, "/note.typ"), Some(PathBuf::from("/vault/typst/note.typ")));
+        assert_eq!(resolve(root, "dir/../note"), Some(PathBuf::from("/vault/typst/note.typ")));
+        assert_eq!(resolve(root, "v1.2 notes"), Some(PathBuf::from("/vault/typst/v1.2 notes.typ")));
+        assert_eq!(resolve(root, "../../../../tmp/x"), None);
+        assert_eq!(resolve(root, "dir/../../pdf/x"), None);
+        assert_eq!(target_for(root, Path::new("/vault/typst/dir/note.typ")), Some("dir/note".into()));
+    }
+
+    #[test]
+    fn link_regions_are_parsed() {
+        let output = r#"[{"dest": "memristor:dir/note", "page": 1, "x": 10, "y": 20.5, "w": 30, "h": 12}]"#;
+        let regions = parse_link_regions(output);
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].note_target(), Some("dir/note"));
+        assert!(regions[0].contains(25.0, 25.0));
+        assert!(!regions[0].contains(50.0, 25.0));
+
+        let output = r#"[
+            {"dest": "https://typst.app", "page": 1, "x": 0, "y": 0, "w": 1, "h": 1},
+            {"dest": "", "page": 1, "x": 0, "y": 0, "w": 1, "h": 1, "tp": 3, "ty": 40},
+            {"dest": "../data.csv", "page": 1, "x": 0, "y": 0, "w": 1, "h": 1}
+        ]"#;
+        let regions = parse_link_regions(output);
+        assert_eq!(regions[0].target(), LinkTarget::Url("https://typst.app"));
+        assert_eq!(regions[1].target(), LinkTarget::Internal { page: 3, y: 40.0 });
+        assert_eq!(regions[2].target(), LinkTarget::File { path: "../data.csv", absolute: false });
+    }
+}
@@ -10,8 +10,25 @@ mod header;
 mod typst;
 mod settings;
 mod error;
+mod replace;
+mod highlighter;
+mod history;
+mod links;
+mod index;
+mod graph;
+mod rename;
+mod check;
+mod tags;
+mod metadata;
+mod outline;
+mod daily;
+mod templates;
+mod sync;
+mod session;
 
-use iced::{self, Element, Task};
+use std::path::Path;
+
+use iced::{self, Element, Subscription, Task, Theme};
 
 use crate::layout::Layout;
 
@@ -38,15 +55,44 @@ impl App {
         Layout::view(&self.layout).map(Message::LayoutMessage)
     }
 
-    fn new() -> Self {
-        App {
-            layout: Layout::default()
-        }
+    fn theme(&self) -> Theme {
+        self.layout.theme()
+    }
+
+    fn subscription(&self) -> Subscription<Message> {
+        self.layout.subscription().map(Message::LayoutMessage)
+    }
+
+    fn new() -> (Self, Task<Message>) {
+        let mut layout = Layout::default();
+        let boot = layout.boot().map(Message::LayoutMessage);
+        (App { layout }, boot)
     }
 }
 
 
 pub fn main() -> iced::Result {
+    // `memristor check <vault>` checks the vault without opening a window
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).is_some_and(|arg| arg == "check") {
+        let Some(vault) = args.get(2) else {
+            eprintln!("Usage: memristor check <vault>");
+            std::process::exit(2);
+        };
+        match check::run_cli(Path::new(vault)) {
+            Ok(true) => std::process::exit(0),
+            Ok(false) => std::process::exit(1),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(2);
+            }
+        }
+    }
+
     iced::application(App::new, App::update, App::view)
+        .subscription(App::subscription)
+        .theme(App::theme)
+        // Closing is handled by the layout so the session can be saved first
+        .exit_on_close_request(false)
         .run()
 }
@@ -0,0 +1,320 @@
+#![allow(dead_code, unused)]
+
+// Structured data about each note, extracted with `typst query` so it
+// reflects the compiled document rather than the raw text. Records are
+// cached in the vault at .memristor/metadata.json and only re-extracted when
+// a note's modification time changes.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use iced::Task;
+use miniserde::{json, Deserialize, Serialize};
+use miniserde::json::Value;
+
+use crate::error::TypstError;
+use crate::filetree;
+use crate::links;
+use crate::outline;
+use crate::typst::TypstContext;
+
+const CACHE_DIR: &str = ".memristor";
+const CACHE_FILE: &str = "metadata.json";
+
+// Every element the metadata is built from, in a single query
+const SELECTOR: &str = "selector(heading).or(metadata).or(cite).or(figure).or(math.equation)";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Heading {
+    pub level: u64,
+    pub text: String,
+    pub label: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetadataBlock {
+    pub label: Option<String>,
+    pub value: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NoteMetadata {
+    // Milliseconds since the epoch, used to tell when the record is stale
+    pub modified: u64,
+    pub title: Option<String>,
+    pub headings: Vec<Heading>,
+    pub labels: Vec<String>,
+    pub metadata: Vec<MetadataBlock>,
+    // The keys of every bibliography entry the note cites
+    pub citations: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Extracted(Vec<(PathBuf, Result<NoteMetadata, TypstError>)>),
+}
+
+// Records are keyed by the note's path relative to the typst directory so
+// the cache survives the vault being moved
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Cache {
+    notes: BTreeMap<String, NoteMetadata>,
+}
+
+#[derive(Debug, Default)]
+pub struct MetadataStore {
+    root_dir: Option<PathBuf>,
+    notes: BTreeMap<PathBuf, NoteMetadata>,
+    extracting: bool,
+    // Set when typst isn't installed so we stop trying
+    unavailable: bool,
+}
+
+impl MetadataStore {
+    pub fn new(root_dir: Option<&Path>) -> Self {
+        let mut store = MetadataStore {
+            root_dir: root_dir.map(Path::to_path_buf),
+            ..MetadataStore::default()
+        };
+        store.load();
+        store
+    }
+
+    fn cache_path(&self) -> Option<PathBuf> {
+        self.root_dir.as_ref().map(|root| root.join(CACHE_DIR).join(CACHE_FILE))
+    }
+
+    fn load(&mut self) {
+        let (Some(root), Some(path)) = (self.root_dir.clone(), self.cache_path()) else {
+            return;
+        };
+        let Ok(contents) = fs::read_to_string(path) else {
+            return;
+        };
+        let cache: Cache = json::from_str(&contents).unwrap_or_default();
+        self.notes = cache.notes.into_iter()
+            .map(|(relative, metadata)| (root.join("typst").join(relative), metadata))
+            .collect();
+    }
+
+    fn save(&self) {
+        let (Some(root), Some(path)) = (&self.root_dir, self.cache_path()) else {
+            return;
+        };
+        let typst_dir = root.join("typst");
+        let cache = Cache {
+            notes: self.notes.iter()
+                .filter_map(|(path, metadata)| {
+                    let relative = path.strip_prefix(&typst_dir).ok()?;
+                    Some((relative.to_string_lossy().replace('\\', "/"), metadata.clone()))
+                })
+                .collect(),
+        };
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = fs::write(path, json::to_string(&cache));
+    }
+
+    pub fn get(&self, note: &Path) -> Option<&NoteMetadata> {
+        self.notes.get(note)
+    }
+
+    pub fn title(&self, note: &Path) -> Option<&str> {
+        self.notes.get(note).and_then(|metadata| metadata.title.as_deref())
+    }
+
+    pub fn titles(&self) -> impl Iterator<Item = (&PathBuf, &str)> {
+        self.notes.iter()
+            .filter_map(|(path, metadata)| Some((path, metadata.title.as_deref()?)))
+    }
+
+    // Starts extracting every note which changed since it was last extracted.
+    // Notes are queried one at a time so a large vault doesn't start a
+    // process per note all at once.
+    pub fn refresh(&mut self) -> Task<Message> {
+        if self.extracting || self.unavailable {
+            return Task::none();
+        }
+        let Some(root) = &self.root_dir else {
+            return Task::none();
+        };
+        let Ok(files) = filetree::typst_files(root) else {
+            return Task::none();
+        };
+
+        let before = self.notes.len();
+        self.notes.retain(|path, _| files.contains(path));
+        if self.notes.len() != before {
+            self.save();
+        }
+
+        let stale: Vec<PathBuf> = files.into_iter()
+            .filter(|file| self.notes.get(file).is_none_or(|metadata| metadata.modified != modified(file)))
+            .collect();
+        if stale.is_empty() {
+            return Task::none();
+        }
+
+        self.extracting = true;
+        Task::perform(
+            async move {
+                let mut results = vec!();
+                for file in stale {
+                    let result = extract(&file).await;
+                    let unavailable = matches!(result, Err(TypstError::TypstNotInstalled));
+                    results.push((file, result));
+                    if unavailable {
+                        break;
+                    }
+                }
+                results
+            },
+            Message::Extracted
+        )
+    }
+
+    pub fn update(&mut self, message: Message) {
+        match message {
+            Message::Extracted(results) => {
+                self.extracting = false;
+                for (file, result) in results {
+                    match result {
+                        Ok(metadata) => { self.notes.insert(file, metadata); }
+                        Err(TypstError::TypstNotInstalled) => { self.unavailable = true; }
+                        // Keep an empty record so broken notes aren't retried until they change
+                        Err(_) => {
+                            let metadata = NoteMetadata { modified: modified(&file), ..Default::default() };
+                            self.notes.insert(file, metadata);
+                        }
+                    }
+                }
+                self.save();
+            }
+        }
+    }
+}
+
+async fn extract(file: &Path) -> Result<NoteMetadata, TypstError> {
+    let source = fs::read_to_string(file).map_err(|err| TypstError::FilesystemError(err.kind()))?;
+    let modified = modified(file);
+    let output = TypstContext::query_elements(links::preprocess(&source), file.to_path_buf(), SELECTOR.into()).await?;
+    let mut metadata = parse_query(&output);
+    metadata.modified = modified;
+    Ok(metadata)
+}
+
+fn modified(path: &Path) -> u64 {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/////////// Logic ///////////////////
+
+// Builds a record from the JSON array of elements `typst query` outputs
+pub fn parse_query(output: &str) -> NoteMetadata {
+    let mut metadata: NoteMetadata = Default::default();
+    let Ok(Value::Array(elements)) = json::from_str::<Value>(output) else {
+        return metadata;
+    };
+
+    for element in elements.iter() {
+        let Value::Object(element) = element else {
+            continue;
+        };
+        let label = match element.get("label") {
+            Some(Value::String(label)) => Some(label.clone()),
+            _ => None,
+        };
+        // Positions recorded by the prelude aren't part of the note
+        if label.as_deref() == Some(links::LINK_LABEL) || label.as_deref() == Some(outline::HEADING_LABEL) {
+            continue;
+        }
+        if let Some(label) = &label {
+            metadata.labels.push(label.clone());
+        }
+
+        match element.get("func") {
+            Some(Value::String(func)) if func == "heading" => {
+                let level = match element.get("level").or_else(|| element.get("depth")) {
+                    Some(Value::Number(json::Number::U64(level))) => *level,
+                    _ => 1,
+                };
+                let text = element.get("body").map(plain_text).unwrap_or_default().trim().to_string();
+                if metadata.title.is_none() && level == 1 {
+                    metadata.title = Some(text.clone());
+                }
+                metadata.headings.push(Heading { level, text, label });
+            }
+            Some(Value::String(func)) if func == "metadata" => {
+                let value = element.get("value").cloned().unwrap_or_default();
+                metadata.metadata.push(MetadataBlock { label, value });
+            }
+            Some(Value::String(func)) if func == "cite" => {
+                if let Some(Value::String(key)) = element.get("key") {
+                    let key = key.trim_start_matches('<').trim_end_matches('>').to_string();
+                    if !metadata.citations.contains(&key) {
+                        metadata.citations.push(key);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    metadata
+}
+
+// The text of a content value as typst serialises it
+fn plain_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items.iter().map(plain_text).collect(),
+        Value::Object(object) => {
+            match object.get("func") {
+                Some(Value::String(func)) if func == "space" || func == "linebreak" || func == "parbreak" => {
+                    return " ".into();
+                }
+                _ => {}
+            }
+            ["text", "children", "body"].iter()
+                .find_map(|field| object.get(*field))
+                .map(plain_text)
+                .unwrap_or_default()
+        }
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_output_is_parsed() {
+        let output = r#"[
+            {"func": "metadata", "value": {"dest": "x"}, "label": "<memristor-link>"},
+            {"func": "heading", "level": 2, "body": {"func": "text", "text": "Before"}},
+            {"func": "heading", "level": 1, "label": "<intro>", "body": {"func": "sequence", "children": [
+                {"func": "text", "text": "Hello"}, {"func": "space"}, {"func": "strong", "body": {"func": "text", "text": "world"}}
+            ]}},
+            {"func": "metadata", "value": {"tags": ["a"]}, "label": "<tags>"},
+            {"func": "cite", "key": "<knuth>"},
+            {"func": "cite", "key": "<knuth>"}
+        ]"#;
+        let metadata = parse_query(output);
+        assert_eq!(metadata.title.as_deref(), Some("Hello world"));
+        assert_eq!(metadata.headings, vec![
+            Heading { level: 2, text: "Before".into(), label: None },
+            Heading { level: 1, text: "Hello world".into(), label: Some("<intro>".into()) },
+        ]);
+        assert_eq!(metadata.labels, vec!["<intro>", "<tags>"]);
+        assert_eq!(metadata.metadata.len(), 1);
+        assert_eq!(metadata.citations, vec!["knuth"]);
+    }
+}
@@ -0,0 +1,100 @@
+#![allow(dead_code, unused)]
+
+// The headings of a note. They're parsed from the source so the outline
+// follows typing without waiting for a render, where each heading ended up
+// in the preview comes from metadata the prelude attaches to every heading.
+
+use miniserde::{json, Deserialize};
+
+// Where heading positions are attached in the compiled document
+pub const HEADING_LABEL: &str = "<memristor-heading>";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutlineHeading {
+    pub level: usize,
+    pub text: String,
+    // Zero based line in the source
+    pub line: usize,
+}
+
+// Where a heading ended up in the compiled document, in points
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct HeadingPosition {
+    pub page: usize,
+    pub y: f32,
+}
+
+pub fn parse_heading_positions(query_output: &str) -> Vec<HeadingPosition> {
+    json::from_str(query_output).unwrap_or_default()
+}
+
+// Headings are lines starting with `=`, ignoring raw blocks and comments
+pub fn parse_headings(source: &str) -> Vec<OutlineHeading> {
+    let mut headings = vec!();
+    let mut in_raw_block = false;
+    let mut in_comment = false;
+
+    for (line_number, line) in source.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if in_comment {
+            in_comment = !line.contains("*/");
+            continue;
+        }
+        if let Some(rest) = trimmed.strip_prefix("```") {
+            // A block opened and closed on the same line doesn't change anything
+            if !rest.contains("```") {
+                in_raw_block = !in_raw_block;
+            }
+            continue;
+        }
+        if in_raw_block {
+            continue;
+        }
+        if trimmed.starts_with("/*") {
+            in_comment = !trimmed.contains("*/");
+            continue;
+        }
+
+        let level = trimmed.chars().take_while(|c| *c == '=').count();
+        let rest = &trimmed[level..];
+        if level == 0 || !(rest.is_empty() || rest.starts_with(char::is_whitespace)) {
+            continue;
+        }
+        // Drop a trailing label or comment
+        let mut text = rest;
+        if let Some(index) = text.find("//") {
+            text = &text[..index];
+        }
+        let text = text.trim();
+        let text = match text.rfind('<') {
+            Some(index) if text.ends_with('>') => text[..index].trim(),
+            _ => text,
+        };
+        headings.push(OutlineHeading { level, text: text.to_string(), line: line_number });
+    }
+    headings
+}
+
+// The heading of the section a line is in
+pub fn current_heading(headings: &[OutlineHeading], line: usize) -> Option<usize> {
+    headings.iter().rposition(|heading| heading.line <= line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headings_are_parsed() {
+        let source = "= Title <title>\ntext\n== Section // note\n```\n= not a heading\n```\n/*\n= nor this\n*/\n==not either\n=== Deep";
+        let headings = parse_headings(source);
+        let summary: Vec<(usize, &str, usize)> = headings.iter()
+            .map(|heading| (heading.level, heading.text.as_str(), heading.line))
+            .collect();
+        assert_eq!(summary, vec![(1, "Title", 0), (2, "Section", 2), (3, "Deep", 10)]);
+
+        assert_eq!(current_heading(&headings, 1), Some(0));
+        assert_eq!(current_heading(&headings, 5), Some(1));
+        assert_eq!(current_heading(&headings, 10), Some(2));
+    }
+}
@@ -0,0 +1,332 @@
+#![allow(dead_code, unused)]
+
+// Renaming and moving files without breaking the vault. Every note is
+// searched for references to the file being moved, note links and the
+// paths given to #import, #include, image and friends, and a plan of the
+// rewrites is built so it can be confirmed before anything is written.
+// Moving a note to another folder also rewrites the relative paths inside
+// it so they still point at the same files.
+
+use std::cmp::Reverse;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::FileSystemError;
+use crate::filetree;
+use crate::links;
+use crate::replace;
+
+// Functions which take a path relative to the calling file
+const PATH_FUNCTIONS: [&str; 10] = [
+    "image(", "read(", "json(", "csv(", "yaml(", "toml(", "xml(", "cbor(", "bibliography(", "plugin(",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileChange {
+    // Where the file will be once the rename is done
+    pub path: PathBuf,
+    pub updated: String,
+    pub references: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenamePlan {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub changes: Vec<FileChange>,
+}
+
+impl RenamePlan {
+    // Works out every rewrite needed to move a file under the vault's typst
+    // directory from one path to another
+    pub fn new(root_dir: &Path, from: &Path, to: &Path) -> Result<Self, FileSystemError> {
+        let to = &normalize(to);
+        if !to.starts_with(normalize(&root_dir.join("typst"))) {
+            return Err(FileSystemError::OutsideVaultError { path: to.into() });
+        }
+        if to.exists() {
+            return Err(FileSystemError::FileExistsError { path: to.into() });
+        }
+
+        let mut changes = vec!();
+        for file in filetree::typst_files(root_dir)? {
+            let source = fs::read_to_string(&file)
+                .map_err(|_| FileSystemError::ReadFileError { path: file.clone().into() })?;
+            let destination = if file == from { to } else { file.as_path() };
+            let (updated, references) = rewrite_source(&source, root_dir, &file, destination, from, to);
+            if references > 0 {
+                changes.push(FileChange { path: destination.to_path_buf(), updated, references });
+            }
+        }
+        Ok(RenamePlan { from: from.to_path_buf(), to: to.to_path_buf(), changes })
+    }
+
+    pub fn references(&self) -> usize {
+        self.changes.iter().map(|change| change.references).sum()
+    }
+
+    // A description of the changes for the confirmation dialog
+    pub fn summary(&self, root_dir: &Path) -> String {
+        let typst_dir = root_dir.join("typst");
+        let display = |path: &Path| path.strip_prefix(&typst_dir).unwrap_or(path).to_string_lossy().into_owned();
+
+        let mut summary = format!("Move '{}' to '{}'", display(&self.from), display(&self.to));
+        if self.changes.is_empty() {
+            summary.push_str("\n\nNo references need updating.");
+            return summary;
+        }
+        summary.push_str(&format!(
+            " and update {} reference{} in {} file{}:\n",
+            self.references(),
+            if self.references() == 1 { "" } else { "s" },
+            self.changes.len(),
+            if self.changes.len() == 1 { "" } else { "s" },
+        ));
+        for change in &self.changes {
+            summary.push_str(&format!("\n{} ({})", display(&change.path), change.references));
+        }
+        summary
+    }
+
+    // Moves the file then writes the rewritten notes, the move is undone if
+    // the notes can't be written
+    pub fn apply(&self) -> Result<(), FileSystemError> {
+        if let Some(parent) = self.to.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|_| FileSystemError::CreateDirError { path: parent.into() })?;
+        }
+        fs::rename(&self.from, &self.to)
+            .map_err(|_| FileSystemError::RenameError { path: self.from.clone().into() })?;
+
+        let files: Vec<(PathBuf, String)> = self.changes.iter()
+            .map(|change| (change.path.clone(), change.updated.clone()))
+            .collect();
+        if let Err(err) = replace::write_atomically(&files) {
+            let _ = fs::rename(&self.to, &self.from);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // Every note which is rewritten, at its path after the rename
+    pub fn touched_files(&self) -> Vec<PathBuf> {
+        self.changes.iter().map(|change| change.path.clone()).collect()
+    }
+}
+
+/////////// Logic ///////////////////
+
+// A reference found in a source, with the byte range of the target on its line
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub kind: ReferenceKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReferenceKind {
+    // A vault relative note link
+    Link(String),
+    // A path relative to the file, or to its directory when it starts with /
+    Path(String),
+}
+
+// Rewrites the references in a note at `original` which is going to live at
+// `destination`, returning the new source and how many references changed
+fn rewrite_source(source: &str, root_dir: &Path, original: &Path, destination: &Path, from: &Path, to: &Path) -> (String, usize) {
+    let original_dir = original.parent().unwrap_or(Path::new(""));
+    let destination_dir = destination.parent().unwrap_or(Path::new(""));
+    let moved = original_dir != destination_dir;
+
+    let mut edits: Vec<(Reference, String)> = vec!();
+    for reference in find_references(source) {
+        let replacement = match &reference.kind {
+            ReferenceKind::Link(target) => {
+                if links::resolve(root_dir, target).as_deref() != Some(from) {
+                    continue;
+                }
+                let Some(mut new_target) = links::target_for(root_dir, to) else {
+                    continue;
+                };
+                if target.ends_with(".typ") {
+                    new_target.push_str(".typ");
+                }
+                new_target
+            }
+            ReferenceKind::Path(path) => {
+                let resolved = resolve_path(original, path);
+                if resolved != from && !moved {
+                    continue;
+                }
+                let target = if resolved == from { to } else { resolved.as_path() };
+                let mut new_path = relative_path(destination_dir, target);
+                if path.starts_with('/') && !new_path.starts_with("..") {
+                    new_path.insert(0, '/');
+                }
+                if new_path == *path {
+                    continue;
+                }
+                new_path
+            }
+        };
+        edits.push((reference, replacement));
+    }
+
+    let count = edits.len();
+    if count == 0 {
+        return (source.to_string(), 0);
+    }
+
+    let mut lines: Vec<String> = source.split('\n').map(String::from).collect();
+    // Apply from the end of each line so earlier offsets stay valid
+    edits.sort_by_key(|(reference, _)| Reverse((reference.line, reference.start)));
+    for (reference, replacement) in edits {
+        lines[reference.line].replace_range(reference.start..reference.end, &replacement);
+    }
+    (lines.join("\n"), count)
+}
+
+pub fn find_references(source: &str) -> Vec<Reference> {
+    let mut references: Vec<Reference> = links::find_links(source).into_iter()
+        .map(|link| Reference {
+            line: link.line,
+            start: link.column,
+            end: link.column + link.target.len(),
+            kind: ReferenceKind::Link(link.target),
+        })
+        .collect();
+
+    for (line_number, line) in source.split('\n').enumerate() {
+        let mut in_raw = false;
+        let mut offset = 0;
+        while offset < line.len() {
+            let rest = &line[offset..];
+            let c = rest.chars().next().unwrap();
+            if c == '`' {
+                in_raw = !in_raw;
+            }
+            if !in_raw && rest.starts_with("//") {
+                break;
+            }
+            if !in_raw && c == '"' && takes_path(&line[..offset]) {
+                let start = offset + 1;
+                if let Some(length) = line[start..].find('"') {
+                    // Packages like "@preview/cetz:0.3.0" aren't files in the vault
+                    let is_package = line[start..].starts_with('@');
+                    offset = start + length + 1;
+                    if is_package {
+                        continue;
+                    }
+                    references.push(Reference {
+                        line: line_number,
+                        start,
+                        end: start + length,
+                        kind: ReferenceKind::Path(line[start..start + length].to_string()),
+                    });
+                    continue;
+                }
+            }
+            offset += c.len_utf8();
+        }
+    }
+    references
+}
+
+// Whether a string starting after this text is a file path
+fn takes_path(before: &str) -> bool {
+    let before = before.trim_end();
+    let is_word_end = |word: &str| {
+        before.strip_suffix(word)
+            .is_some_and(|prefix| !prefix.ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == '-'))
+    };
+    is_word_end("import") || is_word_end("include") || PATH_FUNCTIONS.iter().any(|function| is_word_end(function))
+}
+
+// Where a path in a note points. Notes are compiled with their own
+// directory as the root, so paths starting with / are relative to it too.
+pub fn resolve_path(file: &Path, path: &str) -> PathBuf {
+    normalize(&file.parent().unwrap_or(Path::new("")).join(path.trim_start_matches('/')))
+}
+
+// Resolves . and .. without touching the filesystem
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => { normalized.pop(); }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+// The path to `target` from inside `dir`, always with / separators
+pub fn relative_path(dir: &Path, target: &Path) -> String {
+    let dir: Vec<Component> = dir.components().collect();
+    let target: Vec<Component> = target.components().collect();
+    let shared = dir.iter().zip(target.iter()).take_while(|(a, b)| a == b).count();
+
+    let mut parts: Vec<String> = vec!["..".into(); dir.len() - shared];
+    parts.extend(target[shared..].iter().map(|component| component.as_os_str().to_string_lossy().into_owned()));
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn make_vault(files: &[(&str, &str)]) -> TempDir {
+        let vault = TempDir::new("memristor-rename").unwrap();
+        fs::create_dir_all(vault.path().join("pdf")).unwrap();
+        for (path, contents) in files {
+            let path = vault.path().join("typst").join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        vault
+    }
+
+    #[test]
+    fn references_are_rewritten() {
+        let vault = make_vault(&[
+            ("a.typ", "See [[dir/b]] and #note(\"dir/b.typ\")\n#import \"dir/b.typ\": thing\n// [[dir/b]]"),
+            ("dir/b.typ", "#include \"c.typ\"\n#image(\"../pic.png\")\n#import \"@preview/cetz:0.3.0\""),
+            ("dir/c.typ", "#include \"b.typ\""),
+        ]);
+        let typst = vault.path().join("typst");
+        let plan = RenamePlan::new(vault.path(), &typst.join("dir/b.typ"), &typst.join("other/b2.typ")).unwrap();
+        assert_eq!(plan.references(), 5);
+        plan.apply().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(typst.join("a.typ")).unwrap(),
+            "See [[other/b2]] and #note(\"other/b2.typ\")\n#import \"other/b2.typ\": thing\n// [[dir/b]]"
+        );
+        assert_eq!(
+            fs::read_to_string(typst.join("other/b2.typ")).unwrap(),
+            "#include \"../dir/c.typ\"\n#image(\"../pic.png\")\n#import \"@preview/cetz:0.3.0\""
+        );
+        assert_eq!(fs::read_to_string(typst.join("dir/c.typ")).unwrap(), "#include \"../other/b2.typ\"");
+        assert!(!typst.join("dir/b.typ").exists());
+    }
+
+    #[test]
+    fn existing_files_are_not_overwritten() {
+        let vault = make_vault(&[("a.typ", ""), ("b.typ", "")]);
+        let typst = vault.path().join("typst");
+        assert!(RenamePlan::new(vault.path(), &typst.join("a.typ"), &typst.join("b.typ")).is_err());
+        assert!(RenamePlan::new(vault.path(), &typst.join("a.typ"), &typst.join("../a.typ")).is_err());
+    }
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(relative_path(Path::new("/v/a/b"), Path::new("/v/c/d.typ")), "../../c/d.typ");
+        assert_eq!(relative_path(Path::new("/v"), Path::new("/v/d.typ")), "d.typ");
+        assert_eq!(normalize(Path::new("/v/a/../b/./c")), PathBuf::from("/v/b/c"));
+        assert_eq!(resolve_path(Path::new("/v/typst/a.typ"), "/pic.png"), PathBuf::from("/v/typst/pic.png"));
+    }
+}
@@ -0,0 +1,410 @@
+#![allow(dead_code, unused)]
+
+// Vault wide find and replace. Searching produces a list of matches which
+// can be individually deselected, and applying writes every touched file in
+// one go so the vault is never left half replaced.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use iced::{Element, Length, Color, Padding};
+use iced::widget::{row, column, text, text_input, button, checkbox, scrollable, Column};
+
+use crate::error::FileSystemError;
+use crate::filetree;
+use crate::settings::Settings;
+use crate::styles;
+
+pub struct ReplacePanel {
+    query: String,
+    replacement: String,
+
+    // The query the current matches were found with, the query box
+    // can be edited after searching
+    searched: String,
+    root: Option<PathBuf>,
+    matches: Vec<Match>,
+    checkpoint: Option<Checkpoint>,
+    status: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    QueryChanged(String),
+    ReplacementChanged(String),
+    Search,
+    ToggleMatch(usize),
+
+    // These are handled at the Layout level since open files need reloading
+    Apply,
+    Undo,
+}
+
+impl ReplacePanel {
+    pub fn new() -> Self {
+        ReplacePanel {
+            query: String::new(),
+            replacement: String::new(),
+            searched: String::new(),
+            root: None,
+            matches: vec!(),
+            checkpoint: None,
+            status: None,
+        }
+    }
+
+    pub fn update(&mut self, message: Message, settings: &Settings) {
+        match message {
+            Message::QueryChanged(query) => self.query = query,
+            Message::ReplacementChanged(replacement) => self.replacement = replacement,
+            Message::Search => self.search(settings),
+            Message::ToggleMatch(index) => {
+                if let Some(found) = self.matches.get_mut(index) {
+                    found.selected = !found.selected;
+                }
+            }
+            Message::Apply | Message::Undo => {
+                unreachable!("Should be handled in layout");
+            }
+        }
+    }
+
+    fn search(&mut self, settings: &Settings) {
+        self.matches.clear();
+        self.status = None;
+        let Some(root) = settings.root_dir.as_ref().map(PathBuf::from) else {
+            self.status = Some("No directory loaded".into());
+            return;
+        };
+        match find_matches(&root, &self.query) {
+            Ok(matches) => {
+                self.status = Some(format!("{} matches", matches.len()));
+                self.matches = matches;
+                self.searched = self.query.clone();
+                self.root = Some(root);
+            }
+            Err(err) => self.status = Some(err.to_string()),
+        }
+    }
+
+    // The files applying would rewrite
+    pub fn pending_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self.matches.iter()
+            .filter(|found| found.selected)
+            .map(|found| found.file.clone())
+            .collect();
+        files.dedup();
+        files
+    }
+
+    // The files undoing would rewrite
+    pub fn undo_files(&self) -> Vec<PathBuf> {
+        self.checkpoint.as_ref().map(Checkpoint::files).unwrap_or_default()
+    }
+
+    // Returns the files which were rewritten so any open copies can be reloaded
+    pub fn apply(&mut self) -> Vec<PathBuf> {
+        match apply_replacements(&self.matches, &self.searched, &self.replacement) {
+            Ok(checkpoint) => {
+                let touched = checkpoint.files();
+                self.status = Some(format!("Replaced in {} files", touched.len()));
+                self.checkpoint = Some(checkpoint);
+                self.matches.clear();
+                touched
+            }
+            Err(err) => {
+                self.status = Some(err.to_string());
+                vec!()
+            }
+        }
+    }
+
+    pub fn undo(&mut self) -> Vec<PathBuf> {
+        let Some(checkpoint) = self.checkpoint.take() else {
+            return vec!();
+        };
+        match checkpoint.restore() {
+            Ok(()) => {
+                self.status = Some("Replace undone".into());
+                checkpoint.files()
+            }
+            Err(err) => {
+                self.status = Some(err.to_string());
+                self.checkpoint = Some(checkpoint);
+                vec!()
+            }
+        }
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let selected = self.matches.iter().filter(|found| found.selected).count();
+
+        let controls = column![
+            text_input("Find", &self.query)
+                .on_input(Message::QueryChanged)
+                .on_submit(Message::Search),
+            text_input("Replace with", &self.replacement)
+                .on_input(Message::ReplacementChanged)
+                .on_submit(Message::Search),
+            row![
+                button("Find").on_press(Message::Search),
+                button(text(format!("Replace {}", selected)))
+                    .on_press_maybe((selected > 0).then_some(Message::Apply)),
+                button("Undo")
+                    .on_press_maybe(self.checkpoint.as_ref().map(|_| Message::Undo)),
+            ]
+            .spacing(styles::SPACING_SMALL),
+        ]
+        .spacing(styles::SPACING_SMALL);
+
+        let mut results = Column::new().spacing(styles::SPACING_SMALL);
+        if let Some(status) = &self.status {
+            results = results.push(text(status));
+        }
+        for (index, found) in self.matches.iter().enumerate() {
+            results = results.push(self.match_view(index, found));
+        }
+
+        column![
+            controls,
+            scrollable(results).height(Length::Fill),
+        ]
+        .spacing(styles::SPACING_SMALL)
+        .padding(Padding::new(styles::SPACING_SMALL))
+        .into()
+    }
+
+    fn match_view(&self, index: usize, found: &'_ Match) -> Element<'_, Message> {
+        let location = match &self.root {
+            Some(root) => found.file.strip_prefix(root.join("typst")).unwrap_or(&found.file),
+            None => &found.file,
+        };
+        let label = format!("{}:{}", location.to_string_lossy(), found.line + 1);
+        let (before, after) = found.preview(&self.searched, &self.replacement);
+
+        let mut diff = column![
+            checkbox(found.selected)
+                .label(label)
+                .on_toggle(move |_| Message::ToggleMatch(index)),
+        ];
+        if found.selected {
+            diff = diff
+                .push(text(format!("- {}", before.trim())).color(Color::from_rgb(0.7, 0.1, 0.1)))
+                .push(text(format!("+ {}", after.trim())).color(Color::from_rgb(0.1, 0.5, 0.1)));
+        }
+        diff.into()
+    }
+}
+
+
+/////////// Logic ///////////////////
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub file: PathBuf,
+    // Zero based line number
+    pub line: usize,
+    // Byte offset of the match in the file and in its line
+    pub offset: usize,
+    pub column: usize,
+    pub line_text: String,
+    pub selected: bool,
+}
+
+impl Match {
+    // The line before and after replacement, for the diff preview
+    pub fn preview(&self, query: &str, replacement: &str) -> (String, String) {
+        let mut after = self.line_text.clone();
+        after.replace_range(self.column..self.column + query.len(), replacement);
+        (self.line_text.clone(), after)
+    }
+}
+
+// The original and replaced contents of every file touched by a replace,
+// restoring it puts the vault back how it was before the replace
+#[derive(Debug)]
+pub struct Checkpoint {
+    changes: Vec<(PathBuf, String, String)>,
+}
+
+impl Checkpoint {
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.changes.iter().map(|(path, _, _)| path.clone()).collect()
+    }
+
+    pub fn restore(&self) -> Result<(), FileSystemError> {
+        for (path, _, replaced) in self.changes.iter() {
+            let current = fs::read_to_string(path)
+                .map_err(|_| FileSystemError::ReadFileError { path: path.into() })?;
+            if current != *replaced {
+                return Err(FileSystemError::FileChangedError { path: path.into() });
+            }
+        }
+        let originals: Vec<(PathBuf, String)> = self.changes.iter()
+            .map(|(path, original, _)| (path.clone(), original.clone()))
+            .collect();
+        write_atomically(&originals)
+    }
+}
+
+pub fn find_matches(root_dir: &Path, query: &str) -> Result<Vec<Match>, FileSystemError> {
+    let mut matches = vec!();
+    if query.is_empty() {
+        return Ok(matches);
+    }
+
+    for file in filetree::typst_files(root_dir)? {
+        let contents = fs::read_to_string(&file)
+            .map_err(|_| FileSystemError::ReadFileError { path: file.clone().into() })?;
+
+        let mut line_start = 0;
+        for (line_number, line) in contents.split_inclusive('\n').enumerate() {
+            let line_text = line.trim_end_matches(['\r', '\n']);
+            for (column, _) in line_text.match_indices(query) {
+                matches.push(Match {
+                    file: file.clone(),
+                    line: line_number,
+                    offset: line_start + column,
+                    column,
+                    line_text: line_text.to_string(),
+                    selected: true,
+                });
+            }
+            line_start += line.len();
+        }
+    }
+    Ok(matches)
+}
+
+// Applies every selected match, files are checked to be unchanged since the
+// search before anything is written
+pub fn apply_replacements(matches: &[Match], query: &str, replacement: &str) -> Result<Checkpoint, FileSystemError> {
+    let mut by_file: BTreeMap<&Path, Vec<&Match>> = BTreeMap::new();
+    for found in matches.iter().filter(|found| found.selected) {
+        by_file.entry(found.file.as_path()).or_default().push(found);
+    }
+
+    let mut changes = vec!();
+    for (path, file_matches) in by_file {
+        let original = fs::read_to_string(path)
+            .map_err(|_| FileSystemError::ReadFileError { path: path.into() })?;
+
+        let mut replaced = String::with_capacity(original.len());
+        let mut last = 0;
+        for found in file_matches {
+            let end = found.offset + query.len();
+            if original.get(found.offset..end) != Some(query) {
+                return Err(FileSystemError::FileChangedError { path: path.into() });
+            }
+            replaced.push_str(&original[last..found.offset]);
+            replaced.push_str(replacement);
+            last = end;
+        }
+        replaced.push_str(&original[last..]);
+
+        changes.push((path.to_path_buf(), original, replaced));
+    }
+
+    let new_contents: Vec<(PathBuf, String)> = changes.iter()
+        .map(|(path, _, replaced)| (path.clone(), replaced.clone()))
+        .collect();
+    write_atomically(&new_contents)?;
+    Ok(Checkpoint { changes })
+}
+
+// Writes every file to a sibling temporary file first and only renames them
+// into place once all of them have been written. If a rename fails the files
+// which were already replaced are put back.
+pub fn write_atomically(files: &[(PathBuf, String)]) -> Result<(), FileSystemError> {
+    let temp_path = |path: &Path| {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".memristor-tmp");
+        path.with_file_name(name)
+    };
+
+    let mut written = vec!();
+    for (path, contents) in files.iter() {
+        let temp = temp_path(path);
+        if fs::write(&temp, contents).is_err() {
+            for temp in written {
+                let _ = fs::remove_file(temp);
+            }
+            return Err(FileSystemError::WriteFileError { path: path.into() });
+        }
+        written.push(temp);
+    }
+
+    let mut originals = vec!();
+    for ((path, _), temp) in files.iter().zip(written.iter()) {
+        let original = fs::read(path).ok();
+        if fs::rename(temp, path).is_err() {
+            for (path, original) in originals {
+                let _ = fs::write(path, original);
+            }
+            for temp in written.iter() {
+                let _ = fs::remove_file(temp);
+            }
+            return Err(FileSystemError::WriteFileError { path: path.into() });
+        }
+        if let Some(original) = original {
+            originals.push((path, original));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn make_vault(files: &[(&str, &str)]) -> TempDir {
+        let vault = TempDir::new("memristor-test").unwrap();
+        fs::create_dir_all(vault.path().join("typst")).unwrap();
+        fs::create_dir_all(vault.path().join("pdf")).unwrap();
+        for (name, contents) in files {
+            fs::write(vault.path().join("typst").join(name), contents).unwrap();
+        }
+        vault
+    }
+
+    #[test]
+    fn finds_matches_in_test_fs() {
+        let matches = find_matches(Path::new("./test/test_fs/populated"), "lorem").unwrap();
+        assert_eq!(matches.len(), 10);
+        assert!(matches.iter().all(|found| found.file.ends_with("test_file.typ")));
+        assert_eq!(matches[0].line, 2);
+        assert_eq!(matches[0].column, 1);
+    }
+
+    #[test]
+    fn replace_and_undo() {
+        let vault = make_vault(&[
+            ("a.typ", "#foo()\n#foo(1) + foo\n"),
+            ("b.typ", "nothing here\n"),
+        ]);
+        let mut matches = find_matches(vault.path(), "foo").unwrap();
+        assert_eq!(matches.len(), 3);
+        matches[1].selected = false;
+
+        let checkpoint = apply_replacements(&matches, "foo", "bar").unwrap();
+        let a = vault.path().join("typst/a.typ");
+        assert_eq!(fs::read_to_string(&a).unwrap(), "#bar()\n#foo(1) + bar\n");
+        assert_eq!(checkpoint.files(), vec![a.clone()]);
+
+        checkpoint.restore().unwrap();
+        assert_eq!(fs::read_to_string(&a).unwrap(), "#foo()\n#foo(1) + foo\n");
+    }
+
+    #[test]
+    fn stale_matches_are_rejected() {
+        let vault = make_vault(&[("a.typ", "foo\n")]);
+        let matches = find_matches(vault.path(), "foo").unwrap();
+        fs::write(vault.path().join("typst/a.typ"), "changed\n").unwrap();
+
+        let result = apply_replacements(&matches, "foo", "bar");
+        assert!(matches!(result, Err(FileSystemError::FileChangedError { .. })));
+        assert_eq!(fs::read_to_string(vault.path().join("typst/a.typ")).unwrap(), "changed\n");
+    }
+}
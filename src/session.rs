@@ -0,0 +1,103 @@
+#![allow(dead_code, unused)]
+
+// The state of the workspace when the app was last closed, so it can be
+// opened back up the way it was left. It's kept apart from the settings
+// since it changes every run and nobody edits it by hand.
+
+use miniserde::{json, Serialize, Deserialize};
+
+use std::fs;
+
+use crate::settings::{Settings, SettingsError};
+
+const SESSION_FILE: &str = "session.json";
+
+pub const DEFAULT_MENU_RATIO: f32 = 0.25;
+
+// A note open in a tab
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TabSession {
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+    // How far down the preview was scrolled, from 0 to 1
+    pub preview_y: f32,
+}
+
+// Notes open in other panes come after the ones in the main content pane
+// since only that pane is restored
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Session {
+    // The vault the tabs and folders belong to
+    pub root_dir: Option<String>,
+    pub tabs: Vec<TabSession>,
+    pub active: Option<usize>,
+    pub menu_open: bool,
+    pub menu_ratio: f32,
+    pub editor_open: bool,
+    pub preview_open: bool,
+    pub outline_open: bool,
+    // Relative to the typst directory
+    pub expanded_dirs: Vec<String>,
+}
+
+impl Session {
+    pub fn default() -> Self {
+        Session {
+            root_dir: None,
+            tabs: vec!(),
+            active: None,
+            menu_open: true,
+            menu_ratio: DEFAULT_MENU_RATIO,
+            editor_open: false,
+            preview_open: true,
+            outline_open: false,
+            expanded_dirs: vec!(),
+        }
+    }
+
+    pub fn write(&self) -> Result<(), SettingsError> {
+        let path = Settings::config_dir()?.join(SESSION_FILE);
+        fs::write(path, json::to_string(self))?;
+        Ok(())
+    }
+
+    pub fn read() -> Result<Session, SettingsError> {
+        let path = Settings::config_dir()?.join(SESSION_FILE);
+        let file_contents = fs::read_to_string(path)?;
+        Ok(json::from_str(&file_contents)?)
+    }
+
+    // A session saved for another vault only keeps the layout
+    pub fn for_vault(mut self, root_dir: Option<&str>) -> Self {
+        if self.root_dir.as_deref() != root_dir {
+            self.tabs.clear();
+            self.active = None;
+            self.expanded_dirs.clear();
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_round_trip() {
+        let session = Session {
+            root_dir: Some("/vault".into()),
+            tabs: vec![TabSession { path: "/vault/typst/note.typ".into(), line: 3, column: 7, preview_y: 0.5 }],
+            active: Some(0),
+            menu_ratio: 0.3,
+            expanded_dirs: vec!["projects".into(), "projects/memristor".into()],
+            ..Session::default()
+        };
+        let read: Session = json::from_str(&json::to_string(&session)).unwrap();
+        assert_eq!(read, session);
+
+        let other = read.for_vault(Some("/other"));
+        assert!(other.tabs.is_empty() && other.expanded_dirs.is_empty());
+        assert_eq!(other.menu_ratio, 0.3);
+    }
+}
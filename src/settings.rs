@@ -1,16 +1,36 @@
 #![allow(dead_code, unused)]
 
 use miniserde::{json, Serialize, Deserialize, Error};
+use miniserde::json::{Number, Object, Value};
 use thiserror::Error;
 
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
 use std::io::{self, prelude::*};
 use std::path::{PathBuf, Path};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 const CONFIG_DIR: &'static str = ".config/memristor";
 const CONFIG_FILE: &'static str = "config.json";
 
+// Bumped whenever a change to the settings needs existing files upgrading,
+// with a step added to MIGRATIONS for it
+const VERSION: u32 = 1;
+
+// Each step upgrades a file from the version at its index to the next one
+const MIGRATIONS: [fn(&mut Object); VERSION as usize] = [
+    // Files from before settings were versioned only need the version adding
+    |_| {},
+];
+
+// Set when the settings file couldn't be read but wasn't broken, like one
+// from a newer version, so the defaults used instead never replace it
+static KEEP_FILE: AtomicBool = AtomicBool::new(false);
+
+const DEFAULT_DAILY_DIR: &str = "daily";
+const DEFAULT_DAILY_FORMAT: &str = "YYYY-MM-DD";
+
 #[derive(Error, Debug)]
 pub enum SettingsError {
     #[error("Could not find user config directory")]
@@ -21,21 +41,61 @@ pub enum SettingsError {
 
     #[error("Could not deserialize config data")]
     DeserializationError(#[from] Error),
+
+    #[error("The settings file isn't a JSON object")]
+    NotAnObject,
+
+    #[error("The settings file is from a newer version of memristor (version {0})")]
+    NewerVersion(u32),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Settings {
-    pub root_dir: Option<String>
+    // Missing in files from before settings were versioned
+    pub version: Option<u32>,
+    pub root_dir: Option<String>,
+    // Daily notes, the folder is relative to the typst dir, the filename
+    // format uses YYYY, MM and DD and the template is the name of one in the
+    // vault's templates folder. Older settings have a path relative to the
+    // typst dir instead, which still works.
+    pub daily_dir: Option<String>,
+    pub daily_format: Option<String>,
+    pub daily_template: Option<String>,
+    // Used for the {{author}} placeholder in templates
+    pub author: Option<String>,
+    // Default templates for new notes, keyed by folder relative to the typst dir
+    pub folder_templates: Option<BTreeMap<String, String>>,
+    // Whether preview pages which are slow to draw are shown as PNGs, on by default
+    pub raster_preview: Option<bool>,
+    // Dark app theme, previews are rendered light on dark to match
+    pub dark_mode: Option<bool>,
 }
 
 impl Settings {
-    fn default() -> Self {
+    pub fn default() -> Self {
         Settings {
+            version: Some(VERSION),
             root_dir: None,
+            daily_dir: None,
+            daily_format: None,
+            daily_template: None,
+            author: None,
+            folder_templates: None,
+            raster_preview: None,
+            dark_mode: None,
         }
     }
 
-    fn config_path() -> Result<PathBuf, SettingsError> {
+    pub fn daily_dir(&self) -> &str {
+        self.daily_dir.as_deref().unwrap_or(DEFAULT_DAILY_DIR)
+    }
+
+    pub fn daily_format(&self) -> &str {
+        self.daily_format.as_deref().unwrap_or(DEFAULT_DAILY_FORMAT)
+    }
+
+    // Where the settings and anything else the app keeps between runs live
+    pub fn config_dir() -> Result<PathBuf, SettingsError> {
         let mut config_dir_path = env::home_dir().ok_or(
             io::Error::new(io::ErrorKind::NotFound, "Couldn't get Home directory")
         )?;
@@ -44,7 +104,11 @@ impl Settings {
         if !(Path::new(&config_dir_path)).exists() {
             fs::create_dir_all(&config_dir_path).map_err(|err| SettingsError::CouldNotGetConfigDir(err))?;
         };
+        Ok(config_dir_path)
+    }
 
+    fn config_path() -> Result<PathBuf, SettingsError> {
+        let config_dir_path = Self::config_dir()?;
         let config_file_path = config_dir_path.join(CONFIG_FILE);
         if !(Path::new(&config_file_path)).exists() {
             let mut config_file = fs::File::create_new(&config_file_path)?;
@@ -55,20 +119,165 @@ impl Settings {
     }
 
     pub fn write(&self) -> Result<(), SettingsError> {
-        let config_file_path = Self::config_path()?;
+        if KEEP_FILE.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.write_to(&Self::config_path()?)
+    }
+
+    fn write_to(&self, path: &Path) -> Result<(), SettingsError> {
         let mut file = fs::OpenOptions::new()
             .write(true)
+            .create(true)
             .truncate(true)
-            .open(config_file_path)?;
+            .open(path)?;
         file.write_all(json::to_string(self).as_bytes());
         Ok(())
     }
 
     pub fn read() -> Result<Settings, SettingsError> {
-        let config_file_path = Self::config_path()?;
-        let file_contents = fs::read_to_string(&config_file_path)?;
-        let config: Settings = json::from_str(&file_contents)?;
+        Self::read_from(&Self::config_path()?)
+    }
+
+    // Files from older versions are upgraded and written back, if that
+    // fails the upgrade is just done again next time
+    fn read_from(path: &Path) -> Result<Settings, SettingsError> {
+        let file_contents = fs::read_to_string(path)?;
+        let Value::Object(mut object) = json::from_str(&file_contents)? else {
+            return Err(SettingsError::NotAnObject);
+        };
+        let version = migrate(&mut object);
+        let config: Settings = json::from_str(&json::to_string(&object)).map_err(|err| match version {
+            version if version > VERSION => SettingsError::NewerVersion(version),
+            _ => SettingsError::DeserializationError(err),
+        })?;
+        if version < VERSION {
+            let _ = config.write_to(path);
+        }
         Ok(config)
     }
 
+    // Never fails, the defaults are used when the settings can't be read and
+    // the warning says why so it can be shown in the app
+    pub fn load() -> (Settings, Option<String>) {
+        match Self::config_path() {
+            Ok(path) => Self::load_from(&path),
+            Err(err) => (Settings::default(), Some(format!("{}. Using the default settings", err))),
+        }
+    }
+
+    // Only a file which is broken is moved aside and replaced. One from a
+    // newer version is left for that version, its settings are used if they
+    // can be read but nothing is written back.
+    fn load_from(path: &Path) -> (Settings, Option<String>) {
+        let err = match Self::read_from(path) {
+            Ok(settings) => match settings.version {
+                Some(version) if version > VERSION => {
+                    KEEP_FILE.store(true, Ordering::Relaxed);
+                    let warning = format!("{}. Changes to the settings won't be saved", SettingsError::NewerVersion(version));
+                    return (settings, Some(warning));
+                }
+                _ => return (settings, None),
+            },
+            Err(err) => err,
+        };
+        let settings = Settings::default();
+        let warning = match err {
+            SettingsError::DeserializationError(_) | SettingsError::NotAnObject => match back_up(path) {
+                Some(backup) => {
+                    settings.write_to(path);
+                    format!("{}. Using the default settings, the old file was moved to {}", err, backup.display())
+                }
+                None => {
+                    KEEP_FILE.store(true, Ordering::Relaxed);
+                    format!("{}. Using the default settings for now, they won't be saved", err)
+                }
+            },
+            _ => {
+                KEEP_FILE.store(true, Ordering::Relaxed);
+                format!("{}. Using the default settings for now, they won't be saved", err)
+            }
+        };
+        (settings, Some(warning))
+    }
+}
+
+// Moves a file to the first free backup name next to it, so earlier
+// backups aren't overwritten
+fn back_up(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_string_lossy().into_owned();
+    let backup = (0..).map(|count| match count {
+            0 => path.with_file_name(format!("{}.bak", name)),
+            _ => path.with_file_name(format!("{}.bak.{}", name, count)),
+        })
+        .find(|backup| !backup.exists())?;
+    fs::rename(path, &backup).ok()?;
+    Some(backup)
+}
+
+/////////// Logic ///////////////////
+
+// Upgrades the settings to the current version, returning the version the
+// file was at
+fn migrate(object: &mut Object) -> u32 {
+    let version = match object.get("version") {
+        Some(Value::Number(Number::U64(version))) => *version as u32,
+        _ => 0,
+    };
+    for step in MIGRATIONS.iter().skip(version as usize) {
+        step(object);
+    }
+    if version < VERSION {
+        object.insert("version".to_string(), Value::Number(Number::U64(VERSION as u64)));
+    }
+    version
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_settings_are_migrated() {
+        let Value::Object(mut object) = json::from_str(r#"{"root_dir": "/vault"}"#).unwrap() else {
+            panic!("Not an object");
+        };
+        assert_eq!(migrate(&mut object), 0);
+        let settings: Settings = json::from_str(&json::to_string(&object)).unwrap();
+        assert_eq!(settings.version, Some(VERSION));
+        assert_eq!(settings.root_dir.as_deref(), Some("/vault"));
+
+        assert_eq!(migrate(&mut object), VERSION);
+    }
+
+    #[test]
+    fn broken_settings_fall_back_to_defaults() {
+        let dir = tempdir::TempDir::new("memristor-settings").unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+
+        // Broken files are moved aside without replacing earlier backups
+        for (count, backup) in [CONFIG_FILE.to_string() + ".bak", CONFIG_FILE.to_string() + ".bak.1"].iter().enumerate() {
+            fs::write(&path, format!("{{\"root_dir\": {}", count)).unwrap();
+            let (settings, warning) = Settings::load_from(&path);
+            assert!(settings.root_dir.is_none() && warning.is_some());
+            assert_eq!(fs::read_to_string(dir.path().join(backup)).unwrap(), format!("{{\"root_dir\": {}", count));
+            assert_eq!(Settings::read_from(&path).unwrap().version, Some(VERSION));
+        }
+
+        // A newer version's file is left alone
+        let newer = r#"{"version": 99, "root_dir": 5}"#;
+        fs::write(&path, newer).unwrap();
+        let (settings, warning) = Settings::load_from(&path);
+        assert!(settings.root_dir.is_none() && warning.is_some());
+        assert_eq!(fs::read_to_string(&path).unwrap(), newer);
+        assert!(!dir.path().join(CONFIG_FILE.to_string() + ".bak.2").exists());
+
+        // Even when it can still be read
+        let newer = r#"{"version": 99, "root_dir": "/vault", "from_the_future": true}"#;
+        fs::write(&path, newer).unwrap();
+        let (settings, warning) = Settings::load_from(&path);
+        assert_eq!(settings.root_dir.as_deref(), Some("/vault"));
+        assert!(warning.is_some() && KEEP_FILE.load(Ordering::Relaxed));
+        assert_eq!(fs::read_to_string(&path).unwrap(), newer);
+    }
 }
@@ -0,0 +1,217 @@
+#![allow(dead_code, unused)]
+
+// Keeps the editor and preview in step. Before rendering, the start of each
+// top level paragraph gets a marker recording its source line, the prelude
+// turns markers into metadata with their position in the compiled document.
+// Markers go at the start of the line they mark so line numbers in typst's
+// errors don't move. Headings already record their position.
+
+use miniserde::{json, Deserialize};
+
+use crate::outline::{HeadingPosition, OutlineHeading};
+
+// Where line positions are attached in the compiled document
+pub const LINE_LABEL: &str = "<memristor-line>";
+
+// A source line and where it ended up in the compiled document, in points
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SourceMark {
+    // Zero based
+    pub line: usize,
+    // One based like typst's page numbers
+    pub page: usize,
+    pub y: f32,
+}
+
+pub fn parse_marks(query_output: &str) -> Vec<SourceMark> {
+    json::from_str(query_output).unwrap_or_default()
+}
+
+// Adds a marker to each line starting a paragraph of plain text. Only lines
+// which aren't indented or inside brackets opened by code are marked so
+// markers don't end up inside code or content blocks, and lines which only
+// mean something at the start of a line, like numbered list items, are left
+// alone. Markers can't go on the blank line before instead since it would
+// stop being blank and join the paragraphs. Compile errors on a marked line
+// are off by the length of the marker, they aren't shown in the editor.
+pub fn add_line_markers(source: &str) -> String {
+    let mut output = String::with_capacity(source.len());
+    let mut in_raw_block = false;
+    let mut in_comment = false;
+    let mut in_math = false;
+    let mut previous_blank = true;
+    // Brackets opened by code which haven't been closed yet
+    let mut open = vec!();
+
+    for (line_number, line) in source.split('\n').enumerate() {
+        if line_number > 0 {
+            output.push('\n');
+        }
+        let trimmed = line.trim_start();
+        let starts_paragraph = previous_blank
+            && !in_raw_block && !in_comment && !in_math && open.is_empty()
+            && line.len() == trimmed.len()
+            && trimmed.starts_with(|c: char| c.is_alphanumeric() || c == '*' || c == '_')
+            && !is_enum_item(trimmed);
+        if starts_paragraph {
+            output.push_str(&format!("#memristor-line({});", line_number));
+        }
+        output.push_str(line);
+
+        if !in_raw_block && !in_comment && !in_math {
+            track_brackets(line, &mut open);
+        }
+        if in_comment {
+            in_comment = !line.contains("*/");
+        } else if trimmed.starts_with("/*") {
+            in_comment = !trimmed.contains("*/");
+        }
+        if line.matches("```").count() % 2 == 1 {
+            in_raw_block = !in_raw_block;
+        }
+        if !in_raw_block && line.matches('$').count() % 2 == 1 {
+            in_math = !in_math;
+        }
+        previous_blank = trimmed.is_empty();
+    }
+    output
+}
+
+// Keeps track of the brackets still open at the end of a line. Brackets in
+// markup are only counted once a # switches to code, a [ inside code goes
+// back to markup until it's closed.
+fn track_brackets(line: &str, open: &mut Vec<char>) {
+    // Keywords like #let keep the rest of the line in code
+    let mut code_line = None;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        let in_code = matches!(open.last(), Some('{' | '(')) || code_line == Some(open.len());
+        if in_string {
+            match c {
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '"' if in_code => in_string = true,
+            '/' if in_code && chars.peek() == Some(&'/') => break,
+            '`' if !in_code => {
+                for c in chars.by_ref() {
+                    if c == '`' {
+                        break;
+                    }
+                }
+            }
+            '{' | '(' | '[' if in_code => open.push(c),
+            '}' | ')' | ']' if in_code && !open.is_empty() => {
+                open.pop();
+                // Calls like #f(x)[body] go on after their arguments
+                if !matches!(open.last(), Some('{' | '(')) && code_line != Some(open.len()) {
+                    push_call_brackets(&mut chars, open);
+                }
+            }
+            '[' if !open.is_empty() => open.push(c),
+            ']' if !open.is_empty() => {
+                open.pop();
+            }
+            '#' => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+                    name.push(c);
+                    chars.next();
+                }
+                if CODE_KEYWORDS.contains(&name.as_str()) {
+                    code_line = Some(open.len());
+                } else {
+                    push_call_brackets(&mut chars, open);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Keywords which start code that carries on to the end of the line
+const CODE_KEYWORDS: [&str; 10] = ["let", "set", "show", "if", "for", "while", "import", "include", "return", "context"];
+
+fn push_call_brackets(chars: &mut std::iter::Peekable<std::str::Chars>, open: &mut Vec<char>) {
+    if let Some(&c) = chars.peek().filter(|c| matches!(c, '{' | '(' | '[')) {
+        open.push(c);
+        chars.next();
+    }
+}
+
+// A numbered list item like `1. item`, or `1.` on its own
+fn is_enum_item(line: &str) -> bool {
+    let rest = line.trim_start_matches(|c: char| c.is_ascii_digit());
+    rest.len() < line.len()
+        && rest.strip_prefix('.').is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
+
+// Every known position, in source order. Heading positions are matched to
+// the parsed headings by index.
+pub fn source_marks(marks: &[SourceMark], headings: &[OutlineHeading], heading_positions: &[HeadingPosition]) -> Vec<SourceMark> {
+    let mut all: Vec<SourceMark> = marks.to_vec();
+    all.extend(headings.iter().zip(heading_positions.iter())
+        .map(|(heading, position)| SourceMark { line: heading.line, page: position.page, y: position.y }));
+    all.sort_by_key(|mark| mark.line);
+    all
+}
+
+// The last mark at or before a source line
+pub fn mark_for_line(marks: &[SourceMark], line: usize) -> Option<SourceMark> {
+    marks.iter().rev().find(|mark| mark.line <= line).copied()
+}
+
+// The last mark at or above a point in the preview, page is one based
+pub fn mark_for_point(marks: &[SourceMark], page: usize, y: f32) -> Option<SourceMark> {
+    marks.iter()
+        .filter(|mark| (mark.page, mark.y) <= (page, y))
+        .max_by(|a, b| (a.page, a.y).partial_cmp(&(b.page, b.y)).unwrap_or(std::cmp::Ordering::Equal))
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paragraphs_are_marked() {
+        let source = "= Title\nFirst line\nstill first\n\n  indented\n\n```\nraw\n\ncode\n```\n\n$\nx\n\ny\n$\n\n*Last*\n\n1. one\n2. two\n\n2024 was a year";
+        let output = add_line_markers(source);
+        let marked: Vec<usize> = output.split('\n').enumerate()
+            .filter(|(_, line)| line.starts_with("#memristor-line("))
+            .map(|(number, _)| number)
+            .collect();
+        assert_eq!(marked, vec![18, 23]);
+        assert_eq!(output.split('\n').count(), source.split('\n').count());
+
+        let marks = vec![
+            SourceMark { line: 1, page: 1, y: 100.0 },
+            SourceMark { line: 18, page: 2, y: 50.0 },
+        ];
+        assert_eq!(mark_for_line(&marks, 10).map(|mark| mark.line), Some(1));
+        assert_eq!(mark_for_point(&marks, 1, 700.0).map(|mark| mark.line), Some(1));
+        assert_eq!(mark_for_point(&marks, 2, 60.0).map(|mark| mark.line), Some(18));
+        assert_eq!(mark_for_point(&marks, 1, 10.0), None);
+    }
+
+    #[test]
+    fn code_is_not_marked() {
+        let source = "#let s = \"{\"\n\n#let f() = {\n\nlet x = 1\n\nx\n}\n\nAfter\n\n#{\nx\n}\n\n#box(width: 1fr)[\n\ninside\n]\n\nEnd";
+        let marked: Vec<usize> = add_line_markers(source).split('\n').enumerate()
+            .filter(|(_, line)| line.starts_with("#memristor-line("))
+            .map(|(number, _)| number)
+            .collect();
+        assert_eq!(marked, vec![9, 20]);
+    }
+}
@@ -0,0 +1,321 @@
+#![allow(dead_code, unused)]
+
+// Tags let notes be grouped across folders. A note declares them with a
+// labelled metadata block, so they can also be read with
+// `typst query note.typ "<tags>" --field value`:
+//
+//     #metadata((tags: ("project/memristor", "idea"))) <tags>
+//
+// Tags nest with `/`, a note tagged `project/memristor` is also counted
+// under `project`. Tags are read straight from the source when indexing so
+// the vault doesn't need compiling.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use iced::{Element, Length, Padding};
+use iced::widget::{column, mouse_area, row, scrollable, text, Column, Space};
+use iced::mouse::Interaction;
+
+use crate::index::LinkIndex;
+use crate::styles;
+
+pub const TAGS_LABEL: &str = "<tags>";
+const INDENT: f32 = 12.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagEntry {
+    // The full tag, including its parents
+    pub tag: String,
+    pub depth: usize,
+    pub count: usize,
+}
+
+pub struct TagBrowser {
+    entries: Vec<TagEntry>,
+    selected: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    // Selecting the selected tag clears the selection
+    Select(String),
+}
+
+impl TagBrowser {
+    pub fn new() -> Self {
+        TagBrowser {
+            entries: vec!(),
+            selected: None,
+        }
+    }
+
+    pub fn rebuild(&mut self, index: &LinkIndex) {
+        self.entries = tag_tree(index.tagged_notes());
+        if let Some(selected) = &self.selected
+            && !self.entries.iter().any(|entry| entry.tag == *selected)
+        {
+            self.selected = None;
+        }
+    }
+
+    pub fn update(&mut self, message: Message) {
+        match message {
+            Message::Select(tag) => {
+                self.selected = if self.selected.as_ref() == Some(&tag) { None } else { Some(tag) };
+            }
+        }
+    }
+
+    pub fn selected(&self) -> Option<&str> {
+        self.selected.as_deref()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let mut list = Column::new().spacing(2);
+        for entry in self.entries.iter() {
+            let name = entry.tag.rsplit('/').next().unwrap_or_default();
+            let label = if self.selected.as_ref() == Some(&entry.tag) {
+                format!("# {} ({}) ✓", name, entry.count)
+            } else {
+                format!("# {} ({})", name, entry.count)
+            };
+            list = list.push(
+                mouse_area(
+                    row![
+                        Space::new().width(INDENT * entry.depth as f32),
+                        text(label),
+                    ]
+                    .width(Length::Fill)
+                )
+                .on_press(Message::Select(entry.tag.clone()))
+                .interaction(Interaction::Pointer)
+            );
+        }
+
+        column![
+            text("Tags").size(14),
+            scrollable(list).height(Length::Shrink),
+        ]
+        .spacing(styles::SPACING_SMALL / 2.0)
+        .padding(Padding::new(styles::SPACING_SMALL))
+        .into()
+    }
+}
+
+/////////// Logic ///////////////////
+
+// The tags declared in a note's source, in the order they appear
+pub fn parse_tags(source: &str) -> Vec<String> {
+    let source = strip_comments(source);
+    let mut tags = vec!();
+    let mut rest = source.as_str();
+    while let Some(start) = rest.find("#metadata(") {
+        let arguments = &rest[start + "#metadata".len()..];
+        let Some(end) = closing_paren(arguments) else {
+            break;
+        };
+        let after = arguments[end + 1..].trim_start();
+        let body = &arguments[1..end];
+        if after.starts_with(TAGS_LABEL)
+            && let Some(value) = tags_value(body)
+        {
+            for tag in string_literals(value) {
+                let tag = tag.trim().trim_matches('/').to_string();
+                if !tag.is_empty() && !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+        }
+        rest = &arguments[end + 1..];
+    }
+    tags
+}
+
+// The source with // and /* */ comments taken out, so commented out tags
+// don't count. Strings and urls like https://... are left alone.
+fn strip_comments(source: &str) -> String {
+    let mut output = String::with_capacity(source.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut rest = source;
+    while let Some(c) = rest.chars().next() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if rest.starts_with("//") && !output.ends_with(':') {
+            rest = &rest[rest.find('\n').unwrap_or(rest.len())..];
+            continue;
+        } else if rest.starts_with("/*") {
+            rest = rest[2..].find("*/").map_or("", |end| &rest[end + 4..]);
+            continue;
+        } else if c == '"' {
+            in_string = true;
+        }
+        output.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    output
+}
+
+// The index of the paren matching the one the text starts with
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// The value of the tags key of the dictionary the text holds, an array or a
+// single string, wherever the key is in the dictionary
+fn tags_value(text: &str) -> Option<&str> {
+    let text = text.trim();
+    let end = closing_paren(text)?;
+    let entries = text[..end].strip_prefix('(')?;
+
+    // Split into key: value entries at the commas which aren't nested
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut start = 0;
+    let mut entry_ends = vec!();
+    for (index, c) in entries.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => entry_ends.push(index),
+            _ => {}
+        }
+    }
+    entry_ends.push(entries.len());
+
+    for end in entry_ends {
+        let entry = &entries[start..end];
+        start = end + 1;
+        let Some((key, value)) = entry.split_once(':') else {
+            continue;
+        };
+        if key.trim() == "tags" {
+            let value = value.trim();
+            return match value.chars().next()? {
+                '(' => Some(&value[..=closing_paren(value)?]),
+                '"' => Some(value),
+                _ => None,
+            };
+        }
+    }
+    None
+}
+
+fn string_literals(text: &str) -> Vec<String> {
+    text.split('"').skip(1).step_by(2).map(String::from).collect()
+}
+
+// Flattens the tags of every note into a nested list, parents before their
+// children, counting each note once per tag including nested ones
+pub fn tag_tree<'a>(tagged: impl Iterator<Item = (&'a PathBuf, &'a [String])>) -> Vec<TagEntry> {
+    let mut notes: BTreeMap<String, BTreeSet<&PathBuf>> = BTreeMap::new();
+    for (note, tags) in tagged {
+        for tag in tags {
+            let mut prefix = String::new();
+            for part in tag.split('/') {
+                if !prefix.is_empty() {
+                    prefix.push('/');
+                }
+                prefix.push_str(part);
+                notes.entry(prefix.clone()).or_default().insert(note);
+            }
+        }
+    }
+
+    notes.into_iter()
+        .map(|(tag, notes)| TagEntry {
+            depth: tag.matches('/').count(),
+            tag,
+            count: notes.len(),
+        })
+        .collect()
+}
+
+// Whether a note's tags put it under the given tag
+pub fn has_tag(tags: &[String], tag: &str) -> bool {
+    tags.iter().any(|candidate| {
+        candidate == tag || candidate.strip_prefix(tag).is_some_and(|rest| rest.starts_with('/'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_are_parsed() {
+        let source = "= Note\n#metadata((\n  tags: (\"project/memristor\", \"idea\"),\n)) <tags>\n#metadata((tags: (\"other\",))) <other>\n#metadata((tags: \"single\")) <tags>";
+        assert_eq!(parse_tags(source), vec!["project/memristor", "idea", "single"]);
+
+        // Only the tags key counts, wherever it is in the dictionary
+        let source = "#metadata((tags: (\"a\",), title: \"Draft\")) <tags>";
+        assert_eq!(parse_tags(source), vec!["a"]);
+        let source = "#metadata((title: \"x, y: z\", tags: (\"b\", \"c\"), status: \"done\")) <tags>";
+        assert_eq!(parse_tags(source), vec!["b", "c"]);
+
+        // Commented out tags and things which aren't dictionaries are skipped
+        let source = "// #metadata((tags: (\"a\",))) <tags>\n/* #metadata((tags: \"b\")) <tags> */\n#metadata(ä(tags: (\"c\",))) <tags>\n#metadata((tags: \"https://d\")) <tags>";
+        assert_eq!(parse_tags(source), vec!["https://d"]);
+    }
+
+    #[test]
+    fn nested_tags_are_counted_once_per_note() {
+        let a = PathBuf::from("a.typ");
+        let b = PathBuf::from("b.typ");
+        let a_tags = vec!["project/one".to_string(), "project/two".to_string()];
+        let b_tags = vec!["project".to_string()];
+        let entries = tag_tree(vec![(&a, a_tags.as_slice()), (&b, b_tags.as_slice())].into_iter());
+        let summary: Vec<(&str, usize, usize)> = entries.iter()
+            .map(|entry| (entry.tag.as_str(), entry.depth, entry.count))
+            .collect();
+        assert_eq!(summary, vec![("project", 0, 2), ("project/one", 1, 1), ("project/two", 1, 1)]);
+        assert!(has_tag(&a_tags, "project"));
+        assert!(!has_tag(&a_tags, "proj"));
+    }
+}
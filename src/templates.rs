@@ -0,0 +1,182 @@
+#![allow(dead_code, unused)]
+
+// Notes can start from a template in the vault's templates folder, next to
+// typst and pdf so templates aren't indexed or checked as notes. Templates
+// may use these placeholders:
+//
+//     {{title}}   the file name of the new note
+//     {{date}}    YYYY-MM-DD
+//     {{time}}    HH:MM
+//     {{author}}  the author from the settings, or the user name
+//     {{cursor}}  removed, the cursor starts here
+//
+// Folders can have a default template in the settings, used when a note is
+// created without choosing one.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::daily::{self, Date};
+use crate::error::FileSystemError;
+use crate::links;
+use crate::rename;
+use crate::settings::Settings;
+
+pub const TEMPLATES_DIR: &str = "templates";
+const CURSOR: &str = "{{cursor}}";
+
+// The choices offered when creating a note
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateChoice {
+    FolderDefault,
+    Empty,
+    Template(String),
+}
+
+impl fmt::Display for TemplateChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateChoice::FolderDefault => write!(f, "Folder default"),
+            TemplateChoice::Empty => write!(f, "Empty"),
+            TemplateChoice::Template(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+pub struct Placeholders {
+    pub title: String,
+    pub date: String,
+    pub time: String,
+    pub author: String,
+}
+
+impl Placeholders {
+    // Like daily notes, the date and time are local
+    pub fn now(title: &str, settings: &Settings) -> Self {
+        let (today, minutes) = daily::local_now();
+        Placeholders {
+            title: title.to_string(),
+            date: today.format("YYYY-MM-DD"),
+            time: format!("{:02}:{:02}", minutes / 60, minutes % 60),
+            author: settings.author.clone()
+                .or_else(|| env::var("USER").ok())
+                .or_else(|| env::var("USERNAME").ok())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+// The name of every template, relative to the templates folder without the extension
+pub fn list_templates(root_dir: &Path) -> Vec<String> {
+    let templates_dir = root_dir.join(TEMPLATES_DIR);
+    let mut templates = vec!();
+    let mut dirs = vec![templates_dir.clone()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "typ")
+                && let Ok(relative) = path.with_extension("").strip_prefix(&templates_dir)
+            {
+                templates.push(relative.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+    templates.sort();
+    templates
+}
+
+pub fn read_template(root_dir: &Path, name: &str) -> Result<String, FileSystemError> {
+    let path = root_dir.join(TEMPLATES_DIR).join(name.trim_start_matches('/')).with_extension("typ");
+    fs::read_to_string(&path).map_err(|_| FileSystemError::ReadFileError { path: path.into() })
+}
+
+// The template set for the closest folder containing the note, the note is
+// relative to the typst directory
+pub fn folder_default<'a>(settings: &'a Settings, note: &str) -> Option<&'a str> {
+    let folders = settings.folder_templates.as_ref()?;
+    let mut folder = Path::new(note).parent();
+    while let Some(dir) = folder {
+        let key = dir.to_string_lossy().replace('\\', "/");
+        if let Some(template) = folders.get(&key) {
+            return Some(template);
+        }
+        folder = dir.parent();
+    }
+    None
+}
+
+// Creates a note at a path relative to the typst directory, returning the
+// new file and where the cursor should start
+pub fn create_note(root_dir: &Path, settings: &Settings, note: &str, choice: &TemplateChoice)
+    -> Result<(PathBuf, Option<(usize, usize)>), FileSystemError>
+{
+    let note = note.trim().trim_start_matches('/');
+    let Some(path) = links::resolve(root_dir, note) else {
+        return Err(FileSystemError::OutsideVaultError { path: root_dir.join("typst").join(note).into() });
+    };
+    if path.exists() {
+        return Err(FileSystemError::FileExistsError { path: path.into() });
+    }
+
+    let template = match choice {
+        TemplateChoice::FolderDefault => folder_default(settings, note),
+        TemplateChoice::Empty => None,
+        TemplateChoice::Template(name) => Some(name.as_str()),
+    };
+    let (text, cursor) = match template {
+        Some(name) => {
+            let title = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+            fill(&read_template(root_dir, name)?, &Placeholders::now(&title, settings))
+        }
+        None => (String::new(), None),
+    };
+
+    links::create_note(&path)?;
+    fs::write(&path, text)
+        .map_err(|_| FileSystemError::WriteFileError { path: path.clone().into() })?;
+    Ok((path, cursor))
+}
+
+// The text a new note starts with, and the line and column of the cursor
+pub fn fill(template: &str, placeholders: &Placeholders) -> (String, Option<(usize, usize)>) {
+    let text = template
+        .replace("{{title}}", &placeholders.title)
+        .replace("{{date}}", &placeholders.date)
+        .replace("{{time}}", &placeholders.time)
+        .replace("{{author}}", &placeholders.author);
+
+    let Some(index) = text.find(CURSOR) else {
+        return (text, None);
+    };
+    let before = &text[..index];
+    let line = before.matches('\n').count();
+    let column = before.rsplit('\n').next().unwrap_or_default().chars().count();
+    let text = text.replacen(CURSOR, "", 1);
+    (text, Some((line, column)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_are_filled() {
+        let placeholders = Placeholders {
+            title: "Note".into(),
+            date: "2024-02-29".into(),
+            time: "09:30".into(),
+            author: "Ada".into(),
+        };
+        let (text, cursor) = fill("= {{title}}\n{{author}}, {{date}} {{time}}\n\n- {{cursor}}\n", &placeholders);
+        assert_eq!(text, "= Note\nAda, 2024-02-29 09:30\n\n- \n");
+        assert_eq!(cursor, Some((3, 2)));
+    }
+}
@@ -4,21 +4,31 @@
 // for now we're just doing that through the CLI as a subprocess 
 // since typst's Rust interface isn;t stable.
 
+use std::collections::HashMap;
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs::{self, OpenOptions};
 use std::process::{Command, Stdio};
 use std::time::{SystemTime, Instant, Duration};
 
+use miniserde::{json, Deserialize};
+use miniserde::json::Value;
 use tempdir::TempDir;
 
 use crate::error::{TypstError, FileSystemError};
 
 const SECONDS_BETWEEN_RENDER: u64 = 5;
 
+// SVG pages bigger than this (dense plots, lots of glyphs) are slow to draw,
+// they're also rendered as PNGs which are shown instead
+pub const RASTER_THRESHOLD: u64 = 1_000_000;
+
 pub struct TypstContext {
-    pub preview_path: PathBuf,
     pub temp_dir: TempDir,
+    // Whether heavy pages are rasterized
+    pub raster_fallback: bool,
+    // Render previews light on dark
+    pub dark: bool,
 }
 
 impl TypstContext { 
@@ -28,76 +38,244 @@ impl TypstContext {
             TypstError::TempDirError { message: "Couldn't create temporary directory".into() }
         })?;
         Ok(TypstContext {
-            preview_path: temp_dir.path().join("preview{0p}.svg"),
             temp_dir,
+            raster_fallback: true,
+            dark: false,
         })
     }
 
+    // Each open document renders into its own directory so tabs don't
+    // overwrite each other's previews
+    fn preview_dir(&self, id: u64) -> PathBuf {
+        self.temp_dir.path().join(id.to_string())
+    }
+
+    pub fn preview_path(&self, id: u64) -> PathBuf {
+        let preview_dir = self.preview_dir(id);
+        fs::create_dir_all(&preview_dir);
+        preview_dir.join("preview{0p}.svg")
+    }
+
+    pub fn clear_preview(&self, id: u64) {
+        fs::remove_dir_all(self.preview_dir(id));
+    }
+
     pub async fn compile(preview_path: PathBuf, content: String, open_file: PathBuf) -> Result<(), TypstError> {
-        let content_directory = open_file.as_path().parent().unwrap();
-
-        // Start the typst process
-        let mut typst = Command::new("typst")
-            .arg("compile")
-            .arg("--root").arg(content_directory)
-            .arg(preview_path)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::inherit())
-            .spawn()
-            .unwrap();
-
-        // Write to it's stdin
-        let mut stdin = typst.stdin.take().unwrap();
-        stdin.write(content.as_bytes());
-        drop(stdin);
-
-        // Wait for it to finish
-        let status = typst.wait().unwrap();
+        let mut compile = Command::new("typst");
+        compile.arg("compile").arg("-").arg(preview_path);
+        run(compile, &content, &open_file)?;
         Ok(())
     }
 
-    pub fn get_preview_files(&self) -> io::Result<Vec<PathBuf>> {
-        let mut svgs = vec!();
-        let dir_contents = fs::read_dir(self.temp_dir.path())?;
-
-        // Get a list of all the svgs in the directory
-        for entry in dir_contents {
-            let entry = entry?;
-            if entry.file_type()?.is_file() {
-                let filename: PathBuf = entry.file_name().into();
-                if filename.extension().is_some_and(|ext| ext == "svg") {
-                    svgs.push(entry.path());
-                }
+    // Renders the given one based pages as PNGs next to the SVGs. Old PNGs
+    // are removed first so none are left for pages which are no longer heavy.
+    pub async fn compile_png(preview_path: PathBuf, content: String, open_file: PathBuf, pages: Vec<usize>, ppi: f32) -> Result<(), TypstError> {
+        if let Some(dir) = preview_path.parent() {
+            for file in raster_files(dir).into_values() {
+                fs::remove_file(file);
             }
         }
+        if pages.is_empty() {
+            return Ok(());
+        }
+        let pages: Vec<String> = pages.iter().map(usize::to_string).collect();
+        let mut compile = Command::new("typst");
+        compile.arg("compile").arg("-").arg(preview_path.with_extension("png"))
+            .arg("--format").arg("png")
+            .arg("--ppi").arg(ppi.round().to_string())
+            .arg("--pages").arg(pages.join(","));
+        run(compile, &content, &open_file)?;
+        Ok(())
+    }
 
-        // If we didn't find any then we're done
-        if svgs.len() == 0 {
-            return Ok(svgs);
+    // Runs `typst query` on the content, returning the JSON value of every
+    // element the selector matches
+    pub async fn query(content: String, open_file: PathBuf, selector: String) -> Result<String, TypstError> {
+        let mut query = Command::new("typst");
+        query.arg("query").arg("-").arg(selector).arg("--field").arg("value");
+        run(query, &content, &open_file)
+    }
+
+    // Runs `typst query` returning every matching element in full
+    pub async fn query_elements(content: String, open_file: PathBuf, selector: String) -> Result<String, TypstError> {
+        let mut query = Command::new("typst");
+        query.arg("query").arg("-").arg(selector);
+        run(query, &content, &open_file)
+    }
+
+    pub fn get_preview_files(&self, id: u64) -> io::Result<Vec<PathBuf>> {
+        preview_files(&self.preview_dir(id))
+    }
+
+    pub fn get_raster_files(&self, id: u64) -> HashMap<usize, PathBuf> {
+        raster_files(&self.preview_dir(id))
+    }
+}
+
+pub fn preview_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut svgs = vec!();
+    let dir_contents = fs::read_dir(dir)?;
+
+    // Get a list of all the svgs in the directory
+    for entry in dir_contents {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            let filename: PathBuf = entry.file_name().into();
+            if filename.extension().is_some_and(|ext| ext == "svg") {
+                svgs.push(entry.path());
+            }
         }
+    }
+
+    // If we didn't find any then we're done
+    if svgs.len() == 0 {
+        return Ok(svgs);
+    }
 
-        // Make sure they're in numeric order 
-        svgs.sort();
-
-        // Only return files with last edited dates which are equal to or earlier 
-        // than the first file 
-        // This might not be the best way to do this given the system 
-        // clock isn't montonic 
-        let start_date = fs::metadata(&svgs[0])?.modified()?;
-
-        let new_files = svgs.into_iter().take_while(|file| {
-            match fs::metadata(&file) {
-                Err(_) => false,
-                Ok(metadata) => {
-                    match metadata.modified() {
-                        Err(_) => false,
-                        Ok(modified_time) => modified_time >= start_date
-                    }
+    // Make sure they're in numeric order 
+    svgs.sort();
+
+    // Only return files with last edited dates which are equal to or earlier 
+    // than the first file 
+    // This might not be the best way to do this given the system 
+    // clock isn't montonic 
+    let start_date = fs::metadata(&svgs[0])?.modified()?;
+
+    let new_files = svgs.into_iter().take_while(|file| {
+        match fs::metadata(&file) {
+            Err(_) => false,
+            Ok(metadata) => {
+                match metadata.modified() {
+                    Err(_) => false,
+                    Ok(modified_time) => modified_time >= start_date
                 }
             }
-        }).collect();
+        }
+    }).collect();
+
+
+    Ok(new_files)
+}
+
+// The PNG rendered for each page, keyed by one based page number
+fn raster_files(dir: &Path) -> HashMap<usize, PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+    entries.flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+        .filter_map(|path| Some((page_number(&path)?, path)))
+        .collect()
+}
+
+// From the number typst puts in the file name of each page
+pub fn page_number(path: &Path) -> Option<usize> {
+    let stem = path.file_stem()?.to_string_lossy();
+    stem.trim_start_matches(|c: char| !c.is_ascii_digit()).parse().ok()
+}
+
+// An element from `typst query` without --field
+#[derive(Deserialize)]
+struct QueriedElement {
+    label: Option<String>,
+    value: Option<Value>,
+}
+
+// A selector matching every element with one of the labels
+pub fn any_label(labels: &[&str]) -> String {
+    let mut selector = String::new();
+    for (index, label) in labels.iter().enumerate() {
+        selector = match index {
+            0 => format!("selector({})", label),
+            _ => format!("{}.or({})", selector, label),
+        };
+    }
+    selector
+}
+
+// Splits the output of query_elements by label, giving the JSON array of
+// values for each label the same as querying it alone with --field value
+pub fn values_by_label(output: &str) -> HashMap<String, String> {
+    let elements: Vec<QueriedElement> = json::from_str(output).unwrap_or_default();
+    let mut values: HashMap<String, Vec<Value>> = HashMap::new();
+    for element in elements {
+        if let (Some(label), Some(value)) = (element.label, element.value) {
+            values.entry(label).or_default().push(value);
+        }
+    }
+    values.into_iter()
+        .map(|(label, values)| (label, json::to_string(&values)))
+        .collect()
+}
+
+// The one based numbers of the pages too big to draw as SVGs
+pub fn heavy_pages(svgs: &[PathBuf]) -> Vec<usize> {
+    svgs.iter()
+        .filter(|path| fs::metadata(path).is_ok_and(|metadata| metadata.len() > RASTER_THRESHOLD))
+        .filter_map(|path| page_number(path))
+        .collect()
+}
+
+// The size of an svg page in points, from its viewBox
+pub fn svg_page_size(path: &Path) -> Option<(f32, f32)> {
+    svg_size(&fs::read_to_string(path).ok()?)
+}
+
+pub fn svg_size(svg: &str) -> Option<(f32, f32)> {
+    let view_box = svg.split("viewBox=\"").nth(1)?.split('"').next()?;
+    let mut values = view_box.split_whitespace().skip(2).map(|value| value.parse::<f32>());
+    match (values.next(), values.next()) {
+        (Some(Ok(width)), Some(Ok(height))) => Some((width, height)),
+        _ => None,
+    }
+}
+
+// Runs a typst subcommand with the content on stdin, imports are resolved
+// relative to the directory of the file being edited
+fn run(mut command: Command, content: &str, open_file: &Path) -> Result<String, TypstError> {
+    let content_directory = open_file.parent().unwrap();
+
+    // Start the typst process
+    let mut typst = command
+        .arg("--root").arg(content_directory)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|_| TypstError::TypstNotInstalled)?;
+
+    // Write to it's stdin
+    let mut stdin = typst.stdin.take().unwrap();
+    stdin.write_all(content.as_bytes())
+        .map_err(|err| TypstError::FilesystemError(err.kind()))?;
+    drop(stdin);
+
+    // Wait for it to finish
+    let output = typst.wait_with_output()
+        .map_err(|err| TypstError::FilesystemError(err.kind()))?;
+    if !output.status.success() {
+        return Err(TypstError::CompilationError {
+            message: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        Ok(new_files)
+    #[test]
+    fn query_output_is_split_by_label() {
+        assert_eq!(any_label(&["<a>", "<b>", "<c>"]), "selector(<a>).or(<b>).or(<c>)");
+        let output = r#"[
+            {"func": "metadata", "value": {"page": 1}, "label": "<a>"},
+            {"func": "metadata", "value": 2, "label": "<b>"},
+            {"func": "metadata", "value": {"page": 3}, "label": "<a>"}
+        ]"#;
+        let values = values_by_label(output);
+        assert_eq!(values["<a>"], r#"[{"page":1},{"page":3}]"#);
+        assert_eq!(values["<b>"], "[2]");
     }
 }